@@ -0,0 +1,230 @@
+//! Typed annotations on turns
+//!
+//! Reviewers annotate a turn with a `<!-- cmf: label=good score=4 -->`
+//! comment, either leading the reply or after it. Comments are invisible in
+//! rendered markdown and never part of the reply content, so exporters leave
+//! them out. [`Turn::annotations`] reads them with their values typed, and a
+//! [`Filter`] such as `score>=4` or `label=hallucinated` selects turns by
+//! them.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::{meta, Turn};
+
+/// An annotation value, typed from its text
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Bool(bool),
+    Number(f64),
+    /// An inline list, `[a, b]`
+    List(Vec<String>),
+    Text(String),
+}
+
+impl Value {
+    pub fn parse(raw: &str) -> Value {
+        match raw {
+            "true" => return Value::Bool(true),
+            "false" => return Value::Bool(false),
+            _ => {}
+        }
+        if raw.starts_with('[') && raw.ends_with(']') {
+            return Value::List(meta::parse_list(raw));
+        }
+        match raw.parse::<f64>() {
+            Ok(number) if number.is_finite() => Value::Number(number),
+            _ => Value::Text(raw.to_string()),
+        }
+    }
+
+    /// Whether the value is `text`, or a list containing it
+    fn contains(&self, text: &str) -> bool {
+        match self {
+            Value::List(items) => items.iter().any(|item| item == text),
+            Value::Text(value) => value == text,
+            value => *value == Value::parse(text),
+        }
+    }
+}
+
+/// Annotations by key
+pub type Annotations = BTreeMap<String, Value>;
+
+impl Turn {
+    /// The annotations on the reply, with typed values
+    pub fn annotations(&self) -> Annotations {
+        self.meta
+            .iter()
+            .map(|(key, value)| (key.clone(), Value::parse(value)))
+            .collect()
+    }
+
+    /// Whether `label` is among the turn's `label` annotation
+    pub fn has_label(&self, label: &str) -> bool {
+        self.meta
+            .get("label")
+            .is_some_and(|value| Value::parse(value).contains(label))
+    }
+}
+
+/// A condition on one annotation, such as `label=good`, `score>=4`,
+/// `reviewed` (present) or `!reviewed` (absent)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub key: String,
+    pub op: Op,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Present,
+    Absent,
+    /// Equal, or a list containing the value
+    Eq(String),
+    Ne(String),
+    Lt(f64),
+    Le(f64),
+    Gt(f64),
+    Ge(f64),
+}
+
+/// Why a filter expression could not be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterError(pub String);
+
+impl std::fmt::Display for FilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid annotation filter: {}", self.0)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let expr = expr.trim();
+        let error = |why: &str| FilterError(format!("{:?}: {}", expr, why));
+
+        let Some(at) = expr.find(['=', '!', '<', '>']) else {
+            return Ok(Filter::new(expr, Op::Present));
+        };
+        if at == 0 {
+            return match expr.strip_prefix('!') {
+                Some(key) if is_key(key) => Ok(Filter::new(key, Op::Absent)),
+                _ => Err(error("expected a key")),
+            };
+        }
+
+        let (key, rest) = expr.split_at(at);
+        let key = key.trim();
+        let (operator, value) = ["!=", "<=", ">=", "=", "<", ">"]
+            .into_iter()
+            .find_map(|operator| Some((operator, rest.strip_prefix(operator)?)))
+            .ok_or_else(|| error("expected `=`, `!=`, `<`, `<=`, `>` or `>=`"))?;
+        let value = value.trim();
+        if !is_key(key) {
+            return Err(error("expected a key"));
+        }
+
+        let number = || value.parse::<f64>().map_err(|_| error("expected a number"));
+        let op = match operator {
+            "=" => Op::Eq(value.to_string()),
+            "!=" => Op::Ne(value.to_string()),
+            "<" => Op::Lt(number()?),
+            "<=" => Op::Le(number()?),
+            ">" => Op::Gt(number()?),
+            _ => Op::Ge(number()?),
+        };
+        Ok(Filter::new(key, op))
+    }
+}
+
+impl Filter {
+    fn new(key: &str, op: Op) -> Self {
+        Filter {
+            key: key.to_string(),
+            op,
+        }
+    }
+
+    /// Whether the turn's annotations satisfy the condition
+    pub fn matches(&self, turn: &Turn) -> bool {
+        let value = turn.meta.get(&self.key).map(|raw| Value::parse(raw));
+        let number = match value {
+            Some(Value::Number(number)) => Some(number),
+            _ => None,
+        };
+        match &self.op {
+            Op::Present => value.is_some(),
+            Op::Absent => value.is_none(),
+            Op::Eq(expected) => value.is_some_and(|value| value.contains(expected)),
+            Op::Ne(expected) => !value.is_some_and(|value| value.contains(expected)),
+            Op::Lt(limit) => number.is_some_and(|n| n < *limit),
+            Op::Le(limit) => number.is_some_and(|n| n <= *limit),
+            Op::Gt(limit) => number.is_some_and(|n| n > *limit),
+            Op::Ge(limit) => number.is_some_and(|n| n >= *limit),
+        }
+    }
+}
+
+fn is_key(key: &str) -> bool {
+    !key.is_empty() && !key.contains(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Document;
+
+    #[test]
+    fn test_typed_annotations() {
+        let doc = Document::parse(
+            "> Cite it\n<!-- cmf: label=\"[needs-citation, good]\" -->\nThe sky is green.\n\n<!-- cmf: score=4 reviewed=true note=\"see thread\" -->",
+        );
+        let turn = &doc.turns[0];
+        assert_eq!(turn.assistant, "The sky is green.");
+
+        let annotations = turn.annotations();
+        assert_eq!(annotations["score"], Value::Number(4.0));
+        assert_eq!(annotations["reviewed"], Value::Bool(true));
+        assert_eq!(annotations["note"], Value::Text("see thread".to_string()));
+        assert!(turn.has_label("good"));
+        assert!(!turn.has_label("hallucinated"));
+        assert_eq!(
+            serde_json::to_string(&annotations["label"]).unwrap(),
+            r#"["needs-citation","good"]"#
+        );
+        assert_eq!(doc.to_openai_chat()[1].content, "The sky is green.");
+    }
+
+    #[test]
+    fn test_filters() {
+        let doc = Document::parse(
+            "> a\n<!-- cmf: label=good score=4 -->\nA\n\n> b\nB\n<!-- cmf: label=hallucinated score=1.5 -->\n\n> c\nC",
+        );
+        let matching = |expr: &str| -> Vec<&str> {
+            let filter: Filter = expr.parse().unwrap();
+            doc.turns
+                .iter()
+                .filter(|turn| filter.matches(turn))
+                .map(|turn| turn.assistant.as_str())
+                .collect()
+        };
+
+        assert_eq!(matching("label=good"), vec!["A"]);
+        assert_eq!(matching("label!=good"), vec!["B", "C"]);
+        assert_eq!(matching("score>=4"), vec!["A"]);
+        assert_eq!(matching("score < 2"), vec!["B"]);
+        assert_eq!(matching("score"), vec!["A", "B"]);
+        assert_eq!(matching("!label"), vec!["C"]);
+
+        assert!("score>=high".parse::<Filter>().is_err());
+        assert!("=good".parse::<Filter>().is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Anthropic Messages API backend
+
+use serde_json::{json, Value};
+
+use super::{post, response_stream, system_prompt, Backend, BackendError, Framing, Params, Stream};
+use crate::Document;
+
+pub const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+const API_VERSION: &str = "2023-06-01";
+/// The API requires `max_tokens`
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// A Messages API endpoint
+#[derive(Debug, Clone)]
+pub struct Anthropic {
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl Default for Anthropic {
+    fn default() -> Self {
+        Self::new(DEFAULT_BASE_URL)
+    }
+}
+
+impl Anthropic {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: None,
+        }
+    }
+
+    pub fn api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    /// The request body for `doc`
+    pub fn request_body(&self, doc: &Document, params: &Params) -> Value {
+        // The API rejects empty messages, such as the user side of a turn
+        // that opens with the assistant
+        let messages: Vec<Value> = doc
+            .to_openai_chat()
+            .into_iter()
+            .filter(|message| !message.content.is_empty())
+            .map(|message| json!({"role": message.role, "content": message.content}))
+            .collect();
+
+        let mut body = json!({
+            "model": params.model,
+            "max_tokens": params.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            "messages": messages,
+            "stream": true,
+        });
+        if let Some(system) = system_prompt(doc, params) {
+            body["system"] = json!(system);
+        }
+        if let Some(temperature) = params.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if !params.tools.is_empty() {
+            body["tools"] = params
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "name": tool.name,
+                        "description": tool.description,
+                        "input_schema": tool.parameters,
+                    })
+                })
+                .collect();
+        }
+        body
+    }
+}
+
+impl Backend for Anthropic {
+    fn stream(&self, doc: &Document, params: &Params) -> Result<Stream<'_>, BackendError> {
+        let mut headers = vec![("anthropic-version", API_VERSION)];
+        if let Some(ref key) = self.api_key {
+            headers.push(("x-api-key", key));
+        }
+
+        let response = post(
+            &format!("{}/v1/messages", self.base_url),
+            &headers,
+            &self.request_body(doc, params),
+        )?;
+        response_stream(response, Framing::Sse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{collect, mock};
+
+    #[test]
+    fn test_stream_from_mock_server() {
+        let (url, server) = mock::server(
+            "text/event-stream",
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n\
+             event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\n\
+             event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"}}\n\n\
+             event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+        );
+        let backend = Anthropic::new(&url).api_key("secret");
+        let doc = Document::parse("> Hi");
+        let params = Params {
+            model: "claude-test".to_string(),
+            system: Some("Be kind.".to_string()),
+            ..Default::default()
+        };
+
+        let reply = collect(backend.stream(&doc, &params).unwrap(), |_| {
+            Ok::<_, BackendError>(())
+        })
+        .unwrap();
+        assert_eq!(reply.text, "Hello");
+        assert_eq!(reply.stop_reason.as_deref(), Some("end_turn"));
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /v1/messages "));
+        assert!(request.contains("x-api-key: secret"));
+        assert!(request.contains(r#""system":"Be kind.""#));
+        assert!(request.contains(r#""max_tokens":4096"#));
+    }
+}
@@ -0,0 +1,304 @@
+//! Completion backends
+//!
+//! A [`Backend`] turns a document into a stream of assistant [`Event`]s, so
+//! features that send a conversation to a model work with any provider:
+//! - [`OpenAi`] for OpenAI and compatible servers (vLLM, llama.cpp, LM Studio, ...)
+//! - [`Anthropic`] for the Anthropic Messages API
+//! - [`Ollama`] for Ollama's native chat API
+//! - [`Scripted`] replays canned replies, for tests
+
+pub mod anthropic;
+pub mod ollama;
+pub mod openai;
+pub mod scripted;
+
+pub use anthropic::Anthropic;
+pub use ollama::Ollama;
+pub use openai::OpenAi;
+pub use scripted::Scripted;
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read};
+
+use serde_json::Value;
+
+use crate::response::{self, Reply, ReplyBuilder, ResponseError, ToolCall};
+use crate::Document;
+
+/// Request parameters shared by all backends
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params {
+    pub model: String,
+    /// System prompt; defaults to the document's `system` frontmatter
+    pub system: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u32>,
+    pub tools: Vec<Tool>,
+}
+
+/// A tool the model may call
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tool {
+    pub name: String,
+    pub description: String,
+    /// JSON Schema for the arguments
+    pub parameters: Value,
+}
+
+/// A piece of the assistant reply
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Text(String),
+    ToolCall(ToolCall),
+    /// The reply is complete, with the reason the backend gave
+    Stop(Option<String>),
+}
+
+/// The events of one reply, in order
+pub type Stream<'a> = Box<dyn Iterator<Item = Result<Event, BackendError>> + 'a>;
+
+/// An error raised by a backend
+#[derive(Debug)]
+pub enum BackendError {
+    /// The request could not be sent or the server returned an error status
+    Http(String),
+    Response(ResponseError),
+    Io(io::Error),
+    /// A scripted backend has no replies left
+    Exhausted,
+}
+
+impl std::fmt::Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendError::Http(message) => write!(f, "{}", message),
+            BackendError::Response(e) => write!(f, "{}", e),
+            BackendError::Io(e) => write!(f, "{}", e),
+            BackendError::Exhausted => write!(f, "no scripted replies left"),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+impl From<ResponseError> for BackendError {
+    fn from(e: ResponseError) -> Self {
+        BackendError::Response(e)
+    }
+}
+
+impl From<io::Error> for BackendError {
+    fn from(e: io::Error) -> Self {
+        BackendError::Io(e)
+    }
+}
+
+/// A source of assistant replies
+pub trait Backend {
+    /// Request a reply to `doc`
+    fn stream(&self, doc: &Document, params: &Params) -> Result<Stream<'_>, BackendError>;
+}
+
+/// Drain a stream into a reply, calling `on_text` with each piece of text
+///
+/// An error from `on_text` stops reading and is returned as-is.
+pub fn collect<E: From<BackendError>>(
+    stream: Stream<'_>,
+    mut on_text: impl FnMut(&str) -> Result<(), E>,
+) -> Result<Reply, E> {
+    let mut reply = Reply::default();
+    for event in stream {
+        match event? {
+            Event::Text(text) => {
+                on_text(&text)?;
+                reply.text.push_str(&text);
+            }
+            Event::ToolCall(call) => reply.tool_calls.push(call),
+            Event::Stop(reason) => reply.stop_reason = reason,
+        }
+    }
+    Ok(reply)
+}
+
+/// The system prompt for a request
+fn system_prompt<'a>(doc: &'a Document, params: &'a Params) -> Option<&'a str> {
+    params.system.as_deref().or(doc.system())
+}
+
+/// Send a JSON request, turning error statuses into `BackendError::Http`
+fn post(url: &str, headers: &[(&str, &str)], body: &Value) -> Result<ureq::Response, BackendError> {
+    let mut request = ureq::post(url).set("Content-Type", "application/json");
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+
+    match request.send_string(&body.to_string()) {
+        Ok(response) => Ok(response),
+        Err(ureq::Error::Status(status, response)) => {
+            let body = response.into_string().unwrap_or_default();
+            let message = match response::parse(&body) {
+                Err(ResponseError::Api(message)) => message,
+                _ => body.trim().to_string(),
+            };
+            Err(BackendError::Http(format!("HTTP {}: {}", status, message)))
+        }
+        Err(e) => Err(BackendError::Http(e.to_string())),
+    }
+}
+
+/// How a streamed response body is split into events
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Framing {
+    /// `text/event-stream`
+    Sse,
+    /// One JSON object per line
+    Lines,
+}
+
+/// Events from a streamed HTTP response
+fn response_stream(
+    response: ureq::Response,
+    framing: Framing,
+) -> Result<Stream<'static>, BackendError> {
+    // Some servers ignore the request to stream and answer with a single object
+    if response.content_type() == "application/json" {
+        let mut body = String::new();
+        response.into_reader().read_to_string(&mut body)?;
+        let reply = response::parse(&body)?;
+        return Ok(Box::new(reply_events(reply).into_iter().map(Ok)));
+    }
+
+    Ok(Box::new(EventStream {
+        lines: Box::new(BufReader::new(response.into_reader()).lines()),
+        framing,
+        builder: Some(ReplyBuilder::default()),
+        queue: VecDeque::new(),
+    }))
+}
+
+/// The events of a complete reply
+fn reply_events(reply: Reply) -> Vec<Event> {
+    let mut events = Vec::new();
+    if !reply.text.is_empty() {
+        events.push(Event::Text(reply.text));
+    }
+    events.extend(reply.tool_calls.into_iter().map(Event::ToolCall));
+    events.push(Event::Stop(reply.stop_reason));
+    events
+}
+
+struct EventStream {
+    lines: Box<dyn Iterator<Item = io::Result<String>>>,
+    framing: Framing,
+    /// Taken once the body ends
+    builder: Option<ReplyBuilder>,
+    queue: VecDeque<Event>,
+}
+
+impl EventStream {
+    /// Read the payload of the next event, or `None` at the end of the body
+    fn next_payload(&mut self) -> Option<io::Result<String>> {
+        let mut data: Vec<String> = Vec::new();
+        loop {
+            let line = match self.lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => return Some(Err(e)),
+                None if data.is_empty() => return None,
+                None => return Some(Ok(data.join("\n"))),
+            };
+            match self.framing {
+                Framing::Lines if !line.trim().is_empty() => return Some(Ok(line)),
+                Framing::Lines => {}
+                Framing::Sse => {
+                    if let Some(payload) = line.strip_prefix("data:") {
+                        data.push(payload.strip_prefix(' ').unwrap_or(payload).to_string());
+                    } else if line.is_empty() && !data.is_empty() {
+                        return Some(Ok(data.join("\n")));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for EventStream {
+    type Item = Result<Event, BackendError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.queue.pop_front() {
+                return Some(Ok(event));
+            }
+            self.builder.as_ref()?;
+            match self.next_payload() {
+                Some(Ok(payload)) => match self.builder.as_mut()?.push_event(&payload) {
+                    Ok(text) if text.is_empty() => {}
+                    Ok(text) => return Some(Ok(Event::Text(text))),
+                    Err(e) => {
+                        self.builder = None;
+                        return Some(Err(e.into()));
+                    }
+                },
+                Some(Err(e)) => {
+                    self.builder = None;
+                    return Some(Err(e.into()));
+                }
+                None => {
+                    // Tool calls are complete only once the body ends
+                    let reply = self.builder.take()?.finish();
+                    self.queue
+                        .extend(reply.tool_calls.into_iter().map(Event::ToolCall));
+                    self.queue.push_back(Event::Stop(reply.stop_reason));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve one canned response on localhost; the handle yields the request it answered
+    pub fn server(
+        content_type: &'static str,
+        body: &'static str,
+    ) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut payload = vec![0; length];
+            reader.read_exact(&mut payload).unwrap();
+            request.push_str(&String::from_utf8(payload).unwrap());
+
+            let mut stream = reader.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                content_type,
+                body.len(),
+                body
+            )
+            .unwrap();
+            request
+        });
+        (url, handle)
+    }
+}
@@ -0,0 +1,121 @@
+//! Ollama native chat API backend
+
+use serde_json::{json, Value};
+
+use super::{post, response_stream, system_prompt, Backend, BackendError, Framing, Params, Stream};
+use crate::Document;
+
+pub const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+/// An Ollama server
+#[derive(Debug, Clone)]
+pub struct Ollama {
+    base_url: String,
+}
+
+impl Default for Ollama {
+    fn default() -> Self {
+        Self::new(DEFAULT_BASE_URL)
+    }
+}
+
+impl Ollama {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// The request body for `doc`
+    pub fn request_body(&self, doc: &Document, params: &Params) -> Value {
+        let mut messages = Vec::new();
+        if let Some(system) = system_prompt(doc, params) {
+            messages.push(json!({"role": "system", "content": system}));
+        }
+        for message in doc.to_openai_chat() {
+            messages.push(json!(message));
+        }
+
+        let mut options = json!({});
+        if let Some(temperature) = params.temperature {
+            options["temperature"] = json!(temperature);
+        }
+        if let Some(max_tokens) = params.max_tokens {
+            options["num_predict"] = json!(max_tokens);
+        }
+
+        let mut body = json!({
+            "model": params.model,
+            "messages": messages,
+            "stream": true,
+            "options": options,
+        });
+        if !params.tools.is_empty() {
+            body["tools"] = params
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool.name,
+                            "description": tool.description,
+                            "parameters": tool.parameters,
+                        },
+                    })
+                })
+                .collect();
+        }
+        body
+    }
+}
+
+impl Backend for Ollama {
+    fn stream(&self, doc: &Document, params: &Params) -> Result<Stream<'_>, BackendError> {
+        let response = post(
+            &format!("{}/api/chat", self.base_url),
+            &[],
+            &self.request_body(doc, params),
+        )?;
+        response_stream(response, Framing::Lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{collect, mock};
+
+    #[test]
+    fn test_stream_from_mock_server() {
+        let (url, server) = mock::server(
+            "application/x-ndjson",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"Hel\"},\"done\":false}\n\
+             {\"message\":{\"role\":\"assistant\",\"content\":\"lo\"},\"done\":false}\n\
+             {\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\"}\n",
+        );
+        let params = Params {
+            model: "llama3".to_string(),
+            max_tokens: Some(64),
+            ..Default::default()
+        };
+
+        let mut pieces = Vec::new();
+        let reply = collect(
+            Ollama::new(&url)
+                .stream(&Document::parse("> Hi"), &params)
+                .unwrap(),
+            |text| {
+                pieces.push(text.to_string());
+                Ok::<_, BackendError>(())
+            },
+        )
+        .unwrap();
+        assert_eq!(pieces, vec!["Hel", "lo"]);
+        assert_eq!(reply.text, "Hello");
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /api/chat "));
+        assert!(request.contains(r#""num_predict":64"#));
+    }
+}
@@ -0,0 +1,147 @@
+//! OpenAI Chat Completions backend, for OpenAI and compatible servers
+
+use serde_json::{json, Value};
+
+use super::{post, response_stream, system_prompt, Backend, BackendError, Framing, Params, Stream};
+use crate::Document;
+
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// A Chat Completions endpoint
+#[derive(Debug, Clone)]
+pub struct OpenAi {
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl Default for OpenAi {
+    fn default() -> Self {
+        Self::new(DEFAULT_BASE_URL)
+    }
+}
+
+impl OpenAi {
+    /// A backend for `base_url`, e.g. `https://api.openai.com/v1` or `http://localhost:8080/v1`
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: None,
+        }
+    }
+
+    pub fn api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    /// The request body for `doc`
+    pub fn request_body(&self, doc: &Document, params: &Params) -> Value {
+        let mut messages = Vec::new();
+        if let Some(system) = system_prompt(doc, params) {
+            messages.push(json!({"role": "system", "content": system}));
+        }
+        for message in doc.to_openai_chat() {
+            messages.push(json!(message));
+        }
+
+        let mut body = json!({
+            "model": params.model,
+            "messages": messages,
+            "stream": true,
+        });
+        if let Some(temperature) = params.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(max_tokens) = params.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
+        }
+        if !params.tools.is_empty() {
+            body["tools"] = params
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool.name,
+                            "description": tool.description,
+                            "parameters": tool.parameters,
+                        },
+                    })
+                })
+                .collect();
+        }
+        body
+    }
+}
+
+impl Backend for OpenAi {
+    fn stream(&self, doc: &Document, params: &Params) -> Result<Stream<'_>, BackendError> {
+        let authorization = self.api_key.as_ref().map(|key| format!("Bearer {}", key));
+        let mut headers = vec![("Accept", "text/event-stream")];
+        if let Some(ref authorization) = authorization {
+            headers.push(("Authorization", authorization));
+        }
+
+        let response = post(
+            &format!("{}/chat/completions", self.base_url),
+            &headers,
+            &self.request_body(doc, params),
+        )?;
+        response_stream(response, Framing::Sse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{collect, mock, Event};
+
+    #[test]
+    fn test_stream_from_mock_server() {
+        let (url, server) = mock::server(
+            "text/event-stream",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Fo\"}}]}\n\n\
+             data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"ur\"}}]}\n\n\
+             data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"c1\",\"function\":{\"name\":\"calc\",\"arguments\":\"{}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n\
+             data: [DONE]\n\n",
+        );
+        let backend = OpenAi::new(&format!("{}/v1", url)).api_key("secret");
+        let doc = Document::parse("---\nsystem: Be brief.\n---\n> What is 2+2?");
+        let params = Params {
+            model: "test-model".to_string(),
+            ..Default::default()
+        };
+
+        let events: Vec<Event> = backend
+            .stream(&doc, &params)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(events[0], Event::Text("Fo".to_string()));
+        assert!(matches!(events[2], Event::ToolCall(ref call) if call.name == "calc"));
+        assert_eq!(events[3], Event::Stop(Some("tool_calls".to_string())));
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /v1/chat/completions "));
+        assert!(request.contains("Bearer secret"));
+        assert!(request.contains(r#"{"content":"Be brief.","role":"system"}"#));
+    }
+
+    #[test]
+    fn test_non_streaming_answer() {
+        let (url, server) = mock::server(
+            "application/json",
+            r#"{"choices":[{"index":0,"finish_reason":"stop","message":{"content":"Hi"}}]}"#,
+        );
+        let backend = OpenAi::new(&url);
+        let doc = Document::parse("> Hello");
+        let reply = collect(backend.stream(&doc, &Params::default()).unwrap(), |_| {
+            Ok::<_, BackendError>(())
+        })
+        .unwrap();
+        server.join().unwrap();
+        assert_eq!(reply.text, "Hi");
+        assert_eq!(reply.stop_reason.as_deref(), Some("stop"));
+    }
+}
@@ -0,0 +1,104 @@
+//! Deterministic backend that replays canned replies, for tests
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use super::{reply_events, Backend, BackendError, Event, Params, Stream};
+use crate::response::Reply;
+use crate::Document;
+
+/// Replies with each scripted reply in turn, streaming text a word at a time
+///
+/// Requests are recorded so tests can check what was sent.
+#[derive(Debug, Default)]
+pub struct Scripted {
+    replies: Mutex<VecDeque<Reply>>,
+    requests: Mutex<Vec<(Document, Params)>>,
+}
+
+impl Scripted {
+    pub fn new(replies: impl IntoIterator<Item = Reply>) -> Self {
+        Self {
+            replies: Mutex::new(replies.into_iter().collect()),
+            requests: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Plain text replies
+    pub fn text<'a>(replies: impl IntoIterator<Item = &'a str>) -> Self {
+        Self::new(replies.into_iter().map(|text| Reply {
+            text: text.to_string(),
+            stop_reason: Some("stop".to_string()),
+            ..Default::default()
+        }))
+    }
+
+    /// The documents and parameters received so far
+    pub fn requests(&self) -> Vec<(Document, Params)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Backend for Scripted {
+    fn stream(&self, doc: &Document, params: &Params) -> Result<Stream<'_>, BackendError> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((doc.clone(), params.clone()));
+        let reply = self
+            .replies
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(BackendError::Exhausted)?;
+
+        let mut events = Vec::new();
+        for event in reply_events(reply) {
+            match event {
+                Event::Text(text) => events.extend(
+                    text.split_inclusive(' ')
+                        .map(|word| Event::Text(word.to_string())),
+                ),
+                event => events.push(event),
+            }
+        }
+        Ok(Box::new(events.into_iter().map(Ok)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::collect;
+
+    #[test]
+    fn test_replays_in_order() {
+        let backend = Scripted::text(["Hello there", "Bye"]);
+        let doc = Document::parse("> Hi");
+        let params = Params::default();
+
+        let mut pieces = Vec::new();
+        let reply = collect(backend.stream(&doc, &params).unwrap(), |text| {
+            pieces.push(text.to_string());
+            Ok::<_, BackendError>(())
+        })
+        .unwrap();
+        assert_eq!(pieces, vec!["Hello ", "there"]);
+        assert_eq!(reply.text, "Hello there");
+
+        assert_eq!(
+            collect(backend.stream(&doc, &params).unwrap(), |_| Ok::<
+                _,
+                BackendError,
+            >(()))
+            .unwrap()
+            .text,
+            "Bye"
+        );
+        assert!(matches!(
+            backend.stream(&doc, &params),
+            Err(BackendError::Exhausted)
+        ));
+        assert_eq!(backend.requests().len(), 3);
+    }
+}
@@ -0,0 +1,297 @@
+//! Conversation branches as sibling files
+//!
+//! A fork is an ordinary, self-contained CMF file that starts with a copy of
+//! the first turns of its parent. Its frontmatter records where it came
+//! from: `parent` names the parent file relative to the fork's directory and
+//! `forked_at` counts the turns the two share. Every branch can be read,
+//! edited and continued on its own; the links only matter when listing the
+//! branches of a conversation. There is no checked-out branch to record:
+//! the active branch is the file being edited, and [`switch_path`] finds
+//! another one next to it.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{Document, EditError};
+
+/// One file in a family of branches
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub path: PathBuf,
+    /// Number of forks between the root conversation and this one
+    pub depth: usize,
+    /// Turns shared with the parent, or 0 for the root
+    pub forked_at: usize,
+    pub turns: usize,
+}
+
+impl Document {
+    /// A new conversation holding the first `at` turns, linked to `parent`
+    ///
+    /// `parent` is the parent's path relative to where the fork will be saved.
+    pub fn fork(&self, at: usize, parent: &str) -> Result<Document, EditError> {
+        if at > self.turns.len() {
+            return Err(EditError::OutOfRange {
+                index: at,
+                len: self.turns.len(),
+            });
+        }
+        let mut fork = self.clone();
+        fork.turns.truncate(at);
+        fork.meta.insert("parent".to_string(), parent.to_string());
+        fork.meta.insert("forked_at".to_string(), at.to_string());
+        Ok(fork)
+    }
+
+    /// The parent path and number of shared turns, if this is a fork
+    pub fn fork_origin(&self) -> Option<(&str, usize)> {
+        let parent = self.meta.get("parent")?;
+        let at = self.meta.get("forked_at")?.parse().ok()?;
+        Some((parent, at))
+    }
+
+    /// This branch as a standalone conversation, without its fork links
+    pub fn flatten(&self) -> Document {
+        let mut doc = self.clone();
+        doc.meta.remove("parent");
+        doc.meta.remove("forked_at");
+        doc
+    }
+}
+
+/// The first free name of the form `chat-fork.cmf`, `chat-fork-2.cmf`, ...
+/// next to `path`
+pub fn fork_path(path: &Path) -> PathBuf {
+    free_path(path, "fork")
+}
+
+/// The first free name of the form `chat-<suffix>.cmf`,
+/// `chat-<suffix>-2.cmf`, ... next to `path`
+pub(crate) fn free_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "conversation".to_string());
+    (1..)
+        .map(|n| match n {
+            1 => format!("{}-{}.cmf", stem, suffix),
+            n => format!("{}-{}-{}.cmf", stem, suffix, n),
+        })
+        .map(|name| path.with_file_name(name))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+/// The path of the branch `name` to continue in instead of `active`
+///
+/// `name` is taken relative to the directory of `active`, where forks are
+/// saved; the file must exist.
+pub fn switch_path(active: &Path, name: &str) -> io::Result<PathBuf> {
+    let target = active.with_file_name(name);
+    if !target.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not exist", target.display()),
+        ));
+    }
+    Ok(target)
+}
+
+/// Whether `a` and `b` name the same file, however they are spelled
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    same_file_key(a) == same_file_key(b)
+}
+
+/// How a fork saved at `fork` should refer to `parent`
+pub fn parent_link(parent: &Path, fork: &Path) -> String {
+    let same_dir =
+        parent.parent().unwrap_or(Path::new("")) == fork.parent().unwrap_or(Path::new(""));
+    match parent.file_name() {
+        Some(name) if same_dir => name.to_string_lossy().into_owned(),
+        _ => fs::canonicalize(parent)
+            .unwrap_or_else(|_| parent.to_path_buf())
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+/// Every branch of the conversation `path` belongs to, root first
+///
+/// The root is found by following `parent` links; its descendants are the
+/// `.cmf` files in the same directories that link back to it. Children are
+/// listed depth-first, ordered by fork point and then by name.
+pub fn branches(path: &Path) -> io::Result<Vec<Branch>> {
+    let mut root = path.to_path_buf();
+    let mut seen = vec![same_file_key(&root)];
+    while let Some(parent) = parent_of(&root, &read(&root)?) {
+        let key = same_file_key(&parent);
+        if seen.contains(&key) || !parent.exists() {
+            break;
+        }
+        seen.push(key);
+        root = parent;
+    }
+
+    let mut branches = Vec::new();
+    let mut candidates = Vec::new();
+    collect(&root, 0, 0, &mut candidates, &mut branches)?;
+    Ok(branches)
+}
+
+fn collect(
+    path: &Path,
+    depth: usize,
+    forked_at: usize,
+    candidates: &mut Vec<(PathBuf, Document)>,
+    branches: &mut Vec<Branch>,
+) -> io::Result<()> {
+    let doc = read(path)?;
+    branches.push(Branch {
+        path: path.to_path_buf(),
+        depth,
+        forked_at,
+        turns: doc.turns.len(),
+    });
+
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    scan(&dir, candidates)?;
+    let key = same_file_key(path);
+    let mut children: Vec<(PathBuf, usize)> = candidates
+        .iter()
+        .filter(|(_, doc)| {
+            doc.fork_origin()
+                .is_some_and(|(parent, _)| same_file_key(&dir.join(parent)) == key)
+        })
+        .filter(|(child, _)| {
+            !branches
+                .iter()
+                .any(|b| same_file_key(&b.path) == same_file_key(child))
+        })
+        .map(|(child, doc)| (child.clone(), doc.fork_origin().unwrap().1))
+        .collect();
+    children.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+    for (child, at) in children {
+        collect(&child, depth + 1, at, candidates, branches)?;
+    }
+    Ok(())
+}
+
+/// Read every `.cmf` file in `dir` into `candidates`, once per directory
+fn scan(dir: &Path, candidates: &mut Vec<(PathBuf, Document)>) -> io::Result<()> {
+    let listing = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    if candidates
+        .iter()
+        .any(|(path, _)| path.parent() == Some(dir))
+    {
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(listing)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| dir.join(entry.file_name()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "cmf"))
+        .collect();
+    entries.sort();
+    for path in entries {
+        if let Ok(doc) = read(&path) {
+            candidates.push((path, doc));
+        }
+    }
+    Ok(())
+}
+
+fn read(path: &Path) -> io::Result<Document> {
+    Ok(Document::parse(&fs::read_to_string(path)?))
+}
+
+fn parent_of(path: &Path, doc: &Document) -> Option<PathBuf> {
+    let (parent, _) = doc.fork_origin()?;
+    Some(path.parent().unwrap_or(Path::new("")).join(parent))
+}
+
+fn same_file_key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fork_and_flatten() {
+        let doc = Document::parse("---\ntitle: Plans\n---\n\n> One\nA\n\n> Two\nB\n\n> Three\nC");
+        let fork = doc.fork(2, "plans.cmf").unwrap();
+        assert_eq!(fork.turns.len(), 2);
+        assert_eq!(fork.fork_origin(), Some(("plans.cmf", 2)));
+        assert_eq!(fork.title(), Some("Plans"));
+
+        let flat = fork.flatten();
+        assert_eq!(flat.fork_origin(), None);
+        assert_eq!(
+            flat.to_cmf(),
+            "---\ntitle: Plans\n---\n\n> One\nA\n\n> Two\nB"
+        );
+
+        assert!(matches!(
+            doc.fork(4, "plans.cmf"),
+            Err(EditError::OutOfRange { index: 4, len: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_branches() {
+        let dir = std::env::temp_dir().join(format!("cmf-branch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let root = dir.join("chat.cmf");
+        let doc = Document::parse("> One\nA\n\n> Two\nB\n\n> Three\nC");
+        fs::write(&root, doc.to_cmf()).unwrap();
+
+        let late = fork_path(&root);
+        fs::write(&late, doc.fork(2, "chat.cmf").unwrap().to_cmf()).unwrap();
+        let early = fork_path(&root);
+        assert_eq!(early, dir.join("chat-fork-2.cmf"));
+        fs::write(&early, doc.fork(1, "chat.cmf").unwrap().to_cmf()).unwrap();
+        let nested = dir.join("nested.cmf");
+        let fork = Document::parse(&fs::read_to_string(&late).unwrap());
+        fs::write(
+            &nested,
+            fork.fork(1, &parent_link(&late, &nested)).unwrap().to_cmf(),
+        )
+        .unwrap();
+        fs::write(dir.join("other.cmf"), "> Unrelated").unwrap();
+
+        let listed = branches(&nested).unwrap();
+        let summary: Vec<_> = listed
+            .iter()
+            .map(|b| {
+                (
+                    b.path.file_name().unwrap().to_str().unwrap(),
+                    b.depth,
+                    b.forked_at,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("chat.cmf", 0, 0),
+                ("chat-fork-2.cmf", 1, 1),
+                ("chat-fork.cmf", 1, 2),
+                ("nested.cmf", 2, 1),
+            ]
+        );
+
+        assert_eq!(switch_path(&nested, "chat.cmf").unwrap(), root);
+        assert!(switch_path(&nested, "missing.cmf").is_err());
+        let dotted = dir.join(".").join("chat-fork.cmf");
+        assert!(same_file(&dotted, &late));
+        assert!(!same_file(&dotted, &root));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
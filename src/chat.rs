@@ -0,0 +1,165 @@
+//! Chat client: ask a backend for the next reply and write it into the file
+//!
+//! The file is the conversation: the reply is appended to the file as it
+//! streams in, so an interrupted request leaves the partial reply in place.
+//! Tool calls are appended after the text as fenced `tool-call` blocks.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::backend::{self, Backend, BackendError, Params};
+use crate::file::{self, Expect, FileError};
+use crate::response::Reply;
+use crate::Document;
+
+/// An error raised while requesting a reply
+#[derive(Debug)]
+pub enum ChatError {
+    Backend(BackendError),
+    File(FileError),
+    Io(io::Error),
+    /// The last turn already has a reply, or there are no turns
+    NoPrompt,
+}
+
+impl std::fmt::Display for ChatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChatError::Backend(e) => write!(f, "{}", e),
+            ChatError::File(e) => write!(f, "{}", e),
+            ChatError::Io(e) => write!(f, "{}", e),
+            ChatError::NoPrompt => write!(f, "no user turn waiting for a reply"),
+        }
+    }
+}
+
+impl std::error::Error for ChatError {}
+
+impl From<BackendError> for ChatError {
+    fn from(e: BackendError) -> Self {
+        ChatError::Backend(e)
+    }
+}
+
+impl From<FileError> for ChatError {
+    fn from(e: FileError) -> Self {
+        ChatError::File(e)
+    }
+}
+
+impl From<io::Error> for ChatError {
+    fn from(e: io::Error) -> Self {
+        ChatError::Io(e)
+    }
+}
+
+/// Request a reply to the conversation in `path` and append it to the file
+///
+/// `on_delta` sees the reply as it is written, including tool call blocks.
+pub fn reply_to_file(
+    path: &Path,
+    backend: &dyn Backend,
+    params: &Params,
+    mut on_delta: impl FnMut(&str),
+) -> Result<Reply, ChatError> {
+    let content = fs::read_to_string(path)?;
+    let doc = Document::parse(&content);
+    if doc
+        .turns
+        .last()
+        .is_none_or(|turn| !turn.assistant.is_empty())
+    {
+        return Err(ChatError::NoPrompt);
+    }
+
+    let mut len = content.len() as u64;
+    let reply = backend::collect(backend.stream(&doc, params)?, |delta| {
+        len = file::append_assistant_delta(path, delta, Expect::Len(len))?;
+        on_delta(delta);
+        Ok::<_, ChatError>(())
+    })?;
+
+    if !reply.tool_calls.is_empty() {
+        let calls = Reply {
+            tool_calls: reply.tool_calls.clone(),
+            ..Default::default()
+        }
+        .to_markdown();
+        let separator = if reply.text.is_empty() { "" } else { "\n\n" };
+        let tail = format!("{}{}", separator, calls);
+        file::append_assistant_delta(path, &tail, Expect::Len(len))?;
+        on_delta(&tail);
+    }
+
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Scripted;
+    use crate::response::ToolCall;
+
+    fn temp_file(name: &str, content: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("cmf-chat-{}-{}.cmf", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_streams_reply_into_file() {
+        let path = temp_file("stream", "> What is 2+2?");
+        let backend = Scripted::text(["Four.\n> Quote"]);
+
+        let mut seen = String::new();
+        let reply = reply_to_file(&path, &backend, &Params::default(), |delta| {
+            seen.push_str(delta)
+        })
+        .unwrap();
+
+        assert_eq!(seen, reply.text);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "> What is 2+2?\nFour.\n > Quote"
+        );
+        assert_eq!(
+            backend.requests()[0].0.turns[0].user.content,
+            "What is 2+2?"
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_tool_calls_follow_text() {
+        let path = temp_file("tools", "> List files");
+        let backend = Scripted::new([Reply {
+            tool_calls: vec![ToolCall {
+                id: Some("c1".to_string()),
+                name: "ls".to_string(),
+                arguments: "{}".to_string(),
+            }],
+            ..Default::default()
+        }]);
+
+        reply_to_file(&path, &backend, &Params::default(), |_| {}).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "> List files\n```tool-call ls id=c1\n{}\n```"
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_refuses_answered_conversation() {
+        let path = temp_file("answered", "> Hi\nHello!");
+        assert!(matches!(
+            reply_to_file(&path, &Scripted::text(["x"]), &Params::default(), |_| {}),
+            Err(ChatError::NoPrompt)
+        ));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+//! Many conversations in one file
+//!
+//! A collection holds conversations one after another, each starting at a
+//! `---` line that follows a blank line (or starts the file). The `---` may
+//! open the conversation's own frontmatter block:
+//!
+//! ```markdown
+//! ---
+//! title: Greeting
+//! ---
+//!
+//! > Hi
+//! Hello!
+//!
+//! ---
+//!
+//! > Bye
+//! Goodbye!
+//! ```
+//!
+//! Inside a collection, a thematic break in a reply needs another spelling
+//! (`***` or `___`) or no blank line before it, or it starts a new
+//! conversation.
+
+use crate::{meta, Document};
+
+/// Reading and writing collection files
+pub struct Collection;
+
+impl Collection {
+    /// Parse every conversation in a collection
+    ///
+    /// Text between separators that holds nothing but blank lines is
+    /// skipped; anything else becomes a document, even without turns.
+    pub fn parse(input: &str) -> Vec<Document> {
+        let mut chunks: Vec<Vec<&str>> = vec![Vec::new()];
+        let mut offset = 0;
+        let mut after_blank = true;
+        let mut frontmatter_lines = 0;
+
+        for raw in input.split_inclusive('\n') {
+            let start = offset;
+            offset += raw.len();
+            let line = raw.trim_end_matches(['\n', '\r']);
+
+            if frontmatter_lines > 0 {
+                frontmatter_lines -= 1;
+                chunks.last_mut().unwrap().push(line);
+                after_blank = false;
+                continue;
+            }
+
+            if after_blank && line.trim_end() == "---" {
+                if chunks.last().unwrap().iter().any(|l| !l.trim().is_empty()) {
+                    chunks.push(Vec::new());
+                }
+                if let Some((_, lines)) = meta::parse_frontmatter(&input[start..]) {
+                    chunks.last_mut().unwrap().push(line);
+                    frontmatter_lines = lines - 1;
+                }
+                after_blank = false;
+                continue;
+            }
+
+            chunks.last_mut().unwrap().push(line);
+            after_blank = line.trim().is_empty();
+        }
+
+        chunks
+            .into_iter()
+            .filter(|chunk| chunk.iter().any(|line| !line.trim().is_empty()))
+            .map(|chunk| Document::parse(&chunk.join("\n")))
+            .collect()
+    }
+
+    /// Write conversations as a collection, separating them with `---`
+    /// unless their frontmatter already does
+    pub fn to_cmf(docs: &[Document]) -> String {
+        let mut output = String::new();
+        for (i, doc) in docs.iter().enumerate() {
+            let text = doc.to_cmf();
+            if i > 0 {
+                output.push_str("\n\n");
+            }
+            if i > 0 && !text.starts_with("---") {
+                output.push_str("---\n\n");
+            }
+            output.push_str(&text);
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLLECTION: &str = "---\ntitle: Greeting\n---\n\n> Hi\nHello!\n\n---\n\n> Bye\nGoodbye!\n\n---\ntags: [math]\n---\n\n> 2+2?\nFour\n---\nStill four\n";
+
+    #[test]
+    fn test_parse_collection() {
+        let docs = Collection::parse(COLLECTION);
+        assert_eq!(docs.len(), 3);
+        assert_eq!(docs[0].title(), Some("Greeting"));
+        assert_eq!(docs[0].turns[0].assistant, "Hello!");
+        assert!(docs[1].meta.is_empty());
+        assert_eq!(docs[1].turns[0].user.content, "Bye");
+        assert_eq!(docs[2].tags(), vec!["math"]);
+        // Not after a blank line, so a thematic break in the reply
+        assert_eq!(docs[2].turns[0].assistant, "Four\n---\nStill four");
+    }
+
+    #[test]
+    fn test_collection_roundtrip() {
+        let docs = Collection::parse(COLLECTION);
+        assert_eq!(Collection::to_cmf(&docs), COLLECTION.trim_end().to_string());
+        assert_eq!(Collection::parse(&Collection::to_cmf(&docs)), docs);
+
+        // A plain conversation is a collection of one
+        let docs = Collection::parse("> Hi\nHello!");
+        assert_eq!(docs, vec![Document::parse("> Hi\nHello!")]);
+    }
+}
@@ -0,0 +1,145 @@
+//! Replacing early turns with a summary
+//!
+//! [`Document::compact`] asks a backend to summarize all but the last turns
+//! and puts the summary in their place as a single turn. The summary turn
+//! records which turns it stands for with `source_turns` metadata, and, when
+//! the originals are saved elsewhere, where with `source`.
+
+use std::path::{Path, PathBuf};
+
+use crate::backend::{self, Backend, BackendError, Params};
+use crate::{Document, Turn, UserMessage};
+
+/// The question the summary answers, kept as the summary turn's user message
+pub const SUMMARY_PROMPT: &str = "Summarize the conversation so far: the goals, \
+    the decisions made and why, and anything still open. Keep names, numbers and \
+    code that later turns may refer to.";
+
+/// An error raised while compacting a conversation
+#[derive(Debug)]
+pub enum CompactError {
+    Backend(BackendError),
+    /// There are no turns before the ones to keep
+    NothingToCompact,
+    /// The backend replied without any text
+    EmptySummary,
+}
+
+impl std::fmt::Display for CompactError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompactError::Backend(e) => write!(f, "{}", e),
+            CompactError::NothingToCompact => write!(f, "no turns to compact"),
+            CompactError::EmptySummary => write!(f, "the summary came back empty"),
+        }
+    }
+}
+
+impl std::error::Error for CompactError {}
+
+impl From<BackendError> for CompactError {
+    fn from(e: BackendError) -> Self {
+        CompactError::Backend(e)
+    }
+}
+
+impl Document {
+    /// A copy with all but the last `keep_last` turns replaced by a summary
+    /// from `backend`
+    ///
+    /// The final turn is always kept. `source` names where the original
+    /// turns are saved, relative to where the result will be.
+    pub fn compact(
+        &self,
+        keep_last: usize,
+        backend: &dyn Backend,
+        params: &Params,
+        source: Option<&str>,
+    ) -> Result<Document, CompactError> {
+        let at = self.turns.len().saturating_sub(keep_last.max(1));
+        if at == 0 {
+            return Err(CompactError::NothingToCompact);
+        }
+
+        let mut request = self.clone();
+        request.turns.truncate(at);
+        request.turns.push(Turn {
+            user: UserMessage {
+                content: SUMMARY_PROMPT.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let reply = backend::collect(backend.stream(&request, params)?, |_| {
+            Ok::<_, CompactError>(())
+        })?;
+        let summary = reply.text.trim();
+        if summary.is_empty() {
+            return Err(CompactError::EmptySummary);
+        }
+
+        let mut turn = request.turns.pop().unwrap();
+        turn.assistant = summary.to_string();
+        turn.meta
+            .insert("source_turns".to_string(), format!("1-{}", at));
+        if let Some(source) = source {
+            turn.meta.insert("source".to_string(), source.to_string());
+        }
+
+        let mut compacted = self.clone();
+        compacted.turns.splice(..at, [turn]);
+        Ok(compacted)
+    }
+}
+
+/// The first free name of the form `chat-full.cmf`, `chat-full-2.cmf`, ...
+/// next to `path`, for keeping the turns a compaction replaces
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    crate::branch::free_path(path, "full")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Scripted;
+
+    const CHAT: &str = "---\nsystem: Be brief.\n---\n\n> Plan a trip\nWhere to?\n\n> Lisbon\nFour days?\n\n> Yes\nDay 1: Alfama.\n\n> And day 2?";
+
+    #[test]
+    fn test_compact() {
+        let doc = Document::parse(CHAT);
+        let backend = Scripted::text(["A four-day trip to Lisbon, starting in Alfama. "]);
+        let compacted = doc
+            .compact(1, &backend, &Params::default(), Some("chat-full.cmf"))
+            .unwrap();
+
+        let (request, _) = &backend.requests()[0];
+        assert_eq!(request.system(), Some("Be brief."));
+        assert_eq!(request.turns.len(), 4);
+        assert_eq!(request.turns[3].user.content, SUMMARY_PROMPT);
+
+        assert_eq!(compacted.turns.len(), 2);
+        let summary = &compacted.turns[0];
+        assert_eq!(
+            summary.assistant,
+            "A four-day trip to Lisbon, starting in Alfama."
+        );
+        assert_eq!(summary.meta["source_turns"], "1-3");
+        assert_eq!(summary.meta["source"], "chat-full.cmf");
+        assert_eq!(compacted.turns[1].user.content, "And day 2?");
+        assert_eq!(Document::parse(&compacted.to_cmf()), compacted);
+    }
+
+    #[test]
+    fn test_nothing_to_compact() {
+        let doc = Document::parse(CHAT);
+        let backend = Scripted::text([""]);
+        // The final turn is kept even with --keep-last 0
+        let compacted = doc.compact(0, &backend, &Params::default(), None);
+        assert!(matches!(compacted, Err(CompactError::EmptySummary)));
+        assert!(matches!(
+            doc.compact(4, &backend, &Params::default(), None),
+            Err(CompactError::NothingToCompact)
+        ));
+    }
+}
@@ -0,0 +1,451 @@
+//! Turn-aware comparison of two conversations
+//!
+//! Turns are aligned in two passes: identical turns anchor the alignment,
+//! then the turns between anchors are paired up when they are similar
+//! enough to be edits of each other. Everything left over was added or
+//! removed. Paired turns carry a word-level diff of what changed.
+
+use colored::*;
+use serde::Serialize;
+
+use crate::{escape_assistant, Document, Turn};
+
+/// How a turn changed between the two conversations
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    Unchanged,
+    Added,
+    Removed,
+    Edited,
+}
+
+/// A run of text in a word-level diff
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "op", content = "text", rename_all = "snake_case")]
+pub enum Word {
+    Same(String),
+    Added(String),
+    Removed(String),
+}
+
+/// One aligned turn; indices are 0-based
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TurnDiff {
+    pub change: Change,
+    pub old: Option<usize>,
+    pub new: Option<usize>,
+    pub user_edited: bool,
+    pub assistant_changed: bool,
+    pub attribution_changed: bool,
+    pub annotations_changed: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub user: Vec<Word>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub assistant: Vec<Word>,
+}
+
+impl TurnDiff {
+    fn unpaired(change: Change, old: Option<usize>, new: Option<usize>) -> Self {
+        TurnDiff {
+            change,
+            old,
+            new,
+            user_edited: false,
+            assistant_changed: false,
+            attribution_changed: false,
+            annotations_changed: false,
+            user: Vec::new(),
+            assistant: Vec::new(),
+        }
+    }
+
+    fn paired(old: (usize, &Turn), new: (usize, &Turn)) -> Self {
+        let (a, b) = (old.1, new.1);
+        let mut diff = TurnDiff::unpaired(Change::Unchanged, Some(old.0), Some(new.0));
+        diff.user_edited = a.user.content != b.user.content;
+        diff.attribution_changed = a.user.username != b.user.username;
+        diff.assistant_changed = a.assistant != b.assistant || a.alternates != b.alternates;
+        diff.annotations_changed = a.meta != b.meta || a.user.meta != b.user.meta;
+        if diff.user_edited {
+            diff.user = diff_words(&a.user.content, &b.user.content);
+        }
+        if a.assistant != b.assistant {
+            diff.assistant = diff_words(&a.assistant, &b.assistant);
+        }
+        if diff.user_edited
+            || diff.attribution_changed
+            || diff.assistant_changed
+            || diff.annotations_changed
+        {
+            diff.change = Change::Edited;
+        }
+        diff
+    }
+
+    /// The kinds of change, e.g. `user edited, assistant changed`
+    pub fn summary(&self) -> String {
+        let kinds = [
+            (self.user_edited, "user edited"),
+            (self.attribution_changed, "attribution changed"),
+            (self.assistant_changed, "assistant changed"),
+            (self.annotations_changed, "annotations changed"),
+        ];
+        kinds
+            .iter()
+            .filter(|(changed, _)| *changed)
+            .map(|(_, kind)| *kind)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Align the turns of `old` and `new`, in order
+pub fn diff(old: &Document, new: &Document) -> Vec<TurnDiff> {
+    let (a, b) = (&old.turns, &new.turns);
+    let mut anchors = lcs(a.len(), b.len(), |i, j| a[i] == b[j]);
+    anchors.push((a.len(), b.len()));
+
+    let mut diffs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (anchor_i, anchor_j) in anchors {
+        // Pair up similar turns between the anchors
+        let (gap_i, gap_j) = (i, j);
+        let pairs = lcs(anchor_i - gap_i, anchor_j - gap_j, |x, y| {
+            similar(&a[gap_i + x], &b[gap_j + y])
+        });
+        for (x, y) in pairs {
+            let (x, y) = (gap_i + x, gap_j + y);
+            diffs.extend((i..x).map(|k| TurnDiff::unpaired(Change::Removed, Some(k), None)));
+            diffs.extend((j..y).map(|k| TurnDiff::unpaired(Change::Added, None, Some(k))));
+            diffs.push(TurnDiff::paired((x, &a[x]), (y, &b[y])));
+            (i, j) = (x + 1, y + 1);
+        }
+        diffs.extend((i..anchor_i).map(|k| TurnDiff::unpaired(Change::Removed, Some(k), None)));
+        diffs.extend((j..anchor_j).map(|k| TurnDiff::unpaired(Change::Added, None, Some(k))));
+
+        if anchor_i < a.len() {
+            diffs.push(TurnDiff::paired(
+                (anchor_i, &a[anchor_i]),
+                (anchor_j, &b[anchor_j]),
+            ));
+        }
+        (i, j) = (anchor_i + 1, anchor_j + 1);
+    }
+    diffs
+}
+
+/// Two turns are versions of each other if one side is unchanged or they
+/// share most of their words
+pub(crate) fn similar(a: &Turn, b: &Turn) -> bool {
+    if a.user.content == b.user.content || (!a.assistant.is_empty() && a.assistant == b.assistant) {
+        return true;
+    }
+    let words = |turn: &Turn| -> std::collections::BTreeSet<String> {
+        turn.user
+            .content
+            .split_whitespace()
+            .chain(turn.assistant.split_whitespace())
+            .map(str::to_lowercase)
+            .collect()
+    };
+    let (a, b) = (words(a), words(b));
+    let union = a.union(&b).count();
+    union > 0 && a.intersection(&b).count() * 3 >= union
+}
+
+/// Diff two texts by words, keeping whitespace so the result reads back as text
+pub fn diff_words(old: &str, new: &str) -> Vec<Word> {
+    let (a, b) = (tokens(old), tokens(new));
+    let pairs = lcs(a.len(), b.len(), |i, j| a[i] == b[j]);
+
+    let mut words: Vec<Word> = Vec::new();
+    let mut push = |word: Word| match (words.last_mut(), word) {
+        (Some(Word::Same(text)), Word::Same(more))
+        | (Some(Word::Added(text)), Word::Added(more))
+        | (Some(Word::Removed(text)), Word::Removed(more)) => text.push_str(&more),
+        (_, word) => words.push(word),
+    };
+    let (mut i, mut j) = (0, 0);
+    for (x, y) in pairs.into_iter().chain([(a.len(), b.len())]) {
+        a[i..x]
+            .iter()
+            .for_each(|t| push(Word::Removed(t.to_string())));
+        b[j..y]
+            .iter()
+            .for_each(|t| push(Word::Added(t.to_string())));
+        if x < a.len() {
+            push(Word::Same(a[x].to_string()));
+        }
+        (i, j) = (x + 1, y + 1);
+    }
+    words
+}
+
+/// Split into alternating runs of whitespace and non-whitespace
+fn tokens(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut in_space = None;
+    for (i, c) in text.char_indices() {
+        let space = c.is_whitespace();
+        if in_space.is_some_and(|in_space| in_space != space) {
+            tokens.push(&text[start..i]);
+            start = i;
+        }
+        in_space = Some(space);
+    }
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+    tokens
+}
+
+/// Longest common subsequence of two sequences, as index pairs
+pub(crate) fn lcs(n: usize, m: usize, eq: impl Fn(usize, usize) -> bool) -> Vec<(usize, usize)> {
+    // Common ends are matched directly, keeping the table small
+    let mut prefix = 0;
+    while prefix < n && prefix < m && eq(prefix, prefix) {
+        prefix += 1;
+    }
+    let mut suffix = 0;
+    while suffix < n - prefix && suffix < m - prefix && eq(n - 1 - suffix, m - 1 - suffix) {
+        suffix += 1;
+    }
+    let (rows, cols) = (n - prefix - suffix, m - prefix - suffix);
+
+    // lengths[x][y]: LCS length of the middle sections from x and y onwards
+    let mut lengths = vec![vec![0u32; cols + 1]; rows + 1];
+    for x in (0..rows).rev() {
+        for y in (0..cols).rev() {
+            lengths[x][y] = if eq(prefix + x, prefix + y) {
+                lengths[x + 1][y + 1] + 1
+            } else {
+                lengths[x + 1][y].max(lengths[x][y + 1])
+            };
+        }
+    }
+
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|k| (k, k)).collect();
+    let (mut x, mut y) = (0, 0);
+    while x < rows && y < cols {
+        if eq(prefix + x, prefix + y) {
+            pairs.push((prefix + x, prefix + y));
+            x += 1;
+            y += 1;
+        } else if lengths[x + 1][y] >= lengths[x][y + 1] {
+            x += 1;
+        } else {
+            y += 1;
+        }
+    }
+    pairs.extend((0..suffix).map(|k| (n - suffix + k, m - suffix + k)));
+    pairs
+}
+
+/// Render the changed turns for a terminal
+///
+/// Without colors, removed and added words are marked `[-...-]` and `{+...+}`
+/// and whole turns are prefixed with `-` or `+`.
+pub fn format(diffs: &[TurnDiff], old: &Document, new: &Document, use_colors: bool) -> String {
+    let mut sections = Vec::new();
+    for diff in diffs {
+        let section = match diff.change {
+            Change::Unchanged => continue,
+            Change::Added => {
+                let index = diff.new.unwrap();
+                let header = format!("turn {} added", index + 1);
+                let body = prefix_lines(&new.turns[index].to_cmf(), "+ ");
+                format!(
+                    "{}\n{}",
+                    paint(&header, Style::Header, use_colors),
+                    paint(&body, Style::Added, use_colors)
+                )
+            }
+            Change::Removed => {
+                let index = diff.old.unwrap();
+                let header = format!("turn {} removed", index + 1);
+                let body = prefix_lines(&old.turns[index].to_cmf(), "- ");
+                format!(
+                    "{}\n{}",
+                    paint(&header, Style::Header, use_colors),
+                    paint(&body, Style::Removed, use_colors)
+                )
+            }
+            Change::Edited => format_edited(diff, old, new, use_colors),
+        };
+        sections.push(section);
+    }
+    sections.join("\n\n")
+}
+
+fn format_edited(diff: &TurnDiff, old: &Document, new: &Document, use_colors: bool) -> String {
+    let (old_index, new_index) = (diff.old.unwrap(), diff.new.unwrap());
+    let (a, b) = (&old.turns[old_index], &new.turns[new_index]);
+    let position = if old_index == new_index {
+        format!("turn {}", new_index + 1)
+    } else {
+        format!("turn {} -> {}", old_index + 1, new_index + 1)
+    };
+    let mut lines = vec![paint(
+        &format!("{}: {}", position, diff.summary()),
+        Style::Header,
+        use_colors,
+    )];
+
+    let attribution = match (&a.user.username, &b.user.username) {
+        (old, new) if old == new => new.as_ref().map(|name| format!("@{}: ", name)),
+        (old, new) => Some(render_words(
+            &[
+                Word::Removed(old.as_ref().map_or(String::new(), |n| format!("@{}: ", n))),
+                Word::Added(new.as_ref().map_or(String::new(), |n| format!("@{}: ", n))),
+            ],
+            use_colors,
+        )),
+    };
+    let user = if diff.user_edited {
+        render_words(&diff.user, use_colors)
+    } else {
+        b.user.content.clone()
+    };
+    lines.push(prefix_lines(
+        &format!("{}{}", attribution.unwrap_or_default(), user),
+        "> ",
+    ));
+
+    let assistant = if a.assistant != b.assistant {
+        render_words(&diff.assistant, use_colors)
+    } else {
+        b.assistant.clone()
+    };
+    if !assistant.is_empty() {
+        lines.push(escape_assistant(&assistant));
+    }
+    if a.alternates != b.alternates {
+        lines.push(format!(
+            "(alternates: {} -> {})",
+            a.alternates.len(),
+            b.alternates.len()
+        ));
+    }
+    lines.join("\n")
+}
+
+fn render_words(words: &[Word], use_colors: bool) -> String {
+    words
+        .iter()
+        .map(|word| match word {
+            Word::Same(text) => text.clone(),
+            Word::Removed(text) if text.is_empty() => String::new(),
+            Word::Added(text) if text.is_empty() => String::new(),
+            Word::Removed(text) if use_colors => paint(text, Style::Removed, true),
+            Word::Added(text) if use_colors => paint(text, Style::Added, true),
+            Word::Removed(text) => format!("[-{}-]", text),
+            Word::Added(text) => format!("{{+{}+}}", text),
+        })
+        .collect()
+}
+
+enum Style {
+    Header,
+    Added,
+    Removed,
+}
+
+fn paint(text: &str, style: Style, use_colors: bool) -> String {
+    if !use_colors {
+        return text.to_string();
+    }
+    // Color line by line so each line resets cleanly
+    text.split('\n')
+        .map(|line| match style {
+            Style::Header => line.bold().cyan().to_string(),
+            Style::Added => line.green().to_string(),
+            Style::Removed => line.red().strikethrough().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn prefix_lines(text: &str, prefix: &str) -> String {
+    text.split('\n')
+        .map(|line| format!("{}{}", prefix, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A line-oriented view for `git diff` textconv: the canonical CMF of each
+/// turn under a `## Turn N` heading, so hunks name the turn they touch
+pub fn textconv(doc: &Document) -> String {
+    doc.turns
+        .iter()
+        .enumerate()
+        .map(|(i, turn)| format!("## Turn {}\n\n{}\n", i + 1, turn.to_cmf()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(diffs: &[TurnDiff]) -> Vec<(Change, Option<usize>, Option<usize>)> {
+        diffs.iter().map(|d| (d.change, d.old, d.new)).collect()
+    }
+
+    #[test]
+    fn test_align_turns() {
+        let old = Document::parse(
+            "> Hello\nHi!\n\n> What is 2+2?\nIt is 4.\n\n> Tell me a joke\nNo.\n\n> Bye\nCiao",
+        );
+        let new = Document::parse(
+            "> Hello\nHi!\n\n> What is 2+3?\nIt is 5.\n\n> @bob: Bye\nCiao\n\n> One more\nSure",
+        );
+        let diffs = diff(&old, &new);
+        assert_eq!(
+            changes(&diffs),
+            vec![
+                (Change::Unchanged, Some(0), Some(0)),
+                (Change::Edited, Some(1), Some(1)),
+                (Change::Removed, Some(2), None),
+                (Change::Edited, Some(3), Some(2)),
+                (Change::Added, None, Some(3)),
+            ]
+        );
+        assert_eq!(diffs[1].summary(), "user edited, assistant changed");
+        assert_eq!(
+            diffs[1].user,
+            vec![
+                Word::Same("What is ".to_string()),
+                Word::Removed("2+2?".to_string()),
+                Word::Added("2+3?".to_string()),
+            ]
+        );
+        assert_eq!(diffs[3].summary(), "attribution changed");
+    }
+
+    #[test]
+    fn test_format_plain() {
+        let old = Document::parse("> Hi\nThe answer is 4.\n\n> Bye");
+        let new = Document::parse("> Hi\nThe answer is 5.");
+        let output = format(&diff(&old, &new), &old, &new, false);
+        assert_eq!(
+            output,
+            "turn 1: assistant changed\n> Hi\nThe answer is [-4.-]{+5.+}\n\nturn 2 removed\n- > Bye"
+        );
+    }
+
+    #[test]
+    fn test_json_and_textconv() {
+        let old = Document::parse("> Hi\nHello");
+        let new = Document::parse("> Hi\nHello there");
+        let json = serde_json::to_string(&diff(&old, &new)[0]).unwrap();
+        assert!(json.starts_with(r#"{"change":"edited","old":0,"new":0"#));
+        assert!(json.contains(r#"{"op":"added","text":" there"}"#));
+
+        assert_eq!(
+            textconv(&Document::parse("> A\nB\n\n> C")),
+            "## Turn 1\n\n> A\nB\n\n## Turn 2\n\n> C\n"
+        );
+    }
+}
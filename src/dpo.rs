@@ -0,0 +1,124 @@
+//! Preference pairs for DPO-style training
+//!
+//! Every turn with alternates yields one example per rejected candidate.
+//! The prompt is the conversation up to and including the turn's user
+//! message, following the main replies of earlier turns.
+
+use serde::Serialize;
+
+use crate::{ChatMessage, Document, Meta, Turn};
+
+/// One `prompt` / `chosen` / `rejected` row
+#[derive(Debug, Clone, Serialize)]
+pub struct DpoExample {
+    pub prompt: Vec<ChatMessage>,
+    pub chosen: Vec<ChatMessage>,
+    pub rejected: Vec<ChatMessage>,
+}
+
+impl Document {
+    /// Preference pairs from the turns that have alternate replies
+    ///
+    /// The chosen reply is the candidate marked `preferred`, else the highest
+    /// `rating`, else the main reply; every other non-empty candidate is
+    /// rejected against it.
+    pub fn to_dpo(&self) -> Vec<DpoExample> {
+        let mut prompt = Vec::new();
+        if let Some(system) = self.system() {
+            prompt.push(message("system", system));
+        }
+
+        let mut examples = Vec::new();
+        for turn in &self.turns {
+            prompt.push(message("user", &turn.user.content));
+
+            let chosen = chosen(turn);
+            let (chosen_content, _) = turn.candidates().nth(chosen).unwrap();
+            for (i, (content, _)) in turn.candidates().enumerate() {
+                if i == chosen || content.is_empty() || chosen_content.is_empty() {
+                    continue;
+                }
+                examples.push(DpoExample {
+                    prompt: prompt.clone(),
+                    chosen: vec![message("assistant", chosen_content)],
+                    rejected: vec![message("assistant", content)],
+                });
+            }
+
+            if !turn.assistant.is_empty() {
+                prompt.push(message("assistant", &turn.assistant));
+            }
+        }
+        examples
+    }
+}
+
+/// Index of the chosen candidate among `turn.candidates()`
+fn chosen(turn: &Turn) -> usize {
+    let candidates: Vec<&Meta> = turn.candidates().map(|(_, meta)| meta).collect();
+    if let Some(i) = candidates
+        .iter()
+        .position(|meta| meta.get("preferred").is_some_and(|value| value != "false"))
+    {
+        return i;
+    }
+
+    let rating = |meta: &Meta| {
+        meta.get("rating")
+            .and_then(|rating| rating.parse::<f64>().ok())
+    };
+    candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(i, meta)| Some((i, rating(meta)?)))
+        // The first of equally rated candidates wins
+        .fold(None, |best: Option<(usize, f64)>, (i, rating)| match best {
+            Some((_, top)) if top >= rating => best,
+            _ => Some((i, rating)),
+        })
+        .map_or(0, |(i, _)| i)
+}
+
+fn message(role: &str, content: &str) -> ChatMessage {
+    ChatMessage {
+        role: role.to_string(),
+        content: content.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pairs_from_alternates() {
+        let doc = Document::parse(
+            "---\nsystem: Be kind.\n---\n\n> Hi\nHello!\n\n> Write a haiku\nDraft one\n\n<!-- cmf: alternate=1 rating=2 -->\nDraft two\n\n<!-- cmf: alternate=2 preferred=true -->\nDraft three\n\n> Thanks\nAnytime",
+        );
+        let examples = doc.to_dpo();
+        assert_eq!(examples.len(), 2);
+
+        let roles: Vec<&str> = examples[0].prompt.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "user"]);
+        assert_eq!(examples[0].prompt[3].content, "Write a haiku");
+        assert_eq!(examples[0].chosen[0].content, "Draft three");
+        assert_eq!(examples[0].rejected[0].content, "Draft one");
+        assert_eq!(examples[1].rejected[0].content, "Draft two");
+
+        assert_eq!(
+            serde_json::to_string(&examples[1].chosen).unwrap(),
+            r#"[{"role":"assistant","content":"Draft three"}]"#
+        );
+    }
+
+    #[test]
+    fn test_chosen_by_rating() {
+        let doc = Document::parse(
+            "> Q\n<!-- cmf: rating=3 -->\nOkay\n\n<!-- cmf: alternate=1 rating=5 -->\nGreat\n\n<!-- cmf: alternate=2 -->\n",
+        );
+        let examples = doc.to_dpo();
+        assert_eq!(examples.len(), 1);
+        assert_eq!(examples[0].chosen[0].content, "Great");
+        assert_eq!(examples[0].rejected[0].content, "Okay");
+    }
+}
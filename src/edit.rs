@@ -0,0 +1,321 @@
+//! Editing API for documents
+//!
+//! Chat clients grow and rewrite conversations through these methods rather
+//! than touching `doc.turns` directly. Content is validated so that it reads
+//! back as the same turns, and blockquotes in assistant content are escaped
+//! on the way in. Turns that are not edited keep their source span, so
+//! `to_cmf` only rewrites what changed.
+
+use crate::{escape_assistant, meta, Alternate, Document, Meta, Turn, UserMessage};
+
+/// Why an edit was rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditError {
+    /// The username is empty, has surrounding whitespace, or contains `:` or a newline
+    InvalidUsername(String),
+    /// Unattributed user content starting with `@name:` would be read back as attribution
+    AmbiguousAttribution,
+    /// A line of content would be read back as a `<!-- cmf: ... -->` metadata comment
+    MetadataComment,
+    /// There is no turn at this index
+    OutOfRange { index: usize, len: usize },
+    /// The turn has no alternate reply at this index
+    NoAlternate { turn: usize, index: usize },
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditError::InvalidUsername(name) => write!(f, "invalid username: {:?}", name),
+            EditError::AmbiguousAttribution => {
+                write!(f, "user content starting with `@name:` needs a username")
+            }
+            EditError::MetadataComment => {
+                write!(f, "content contains a `<!-- cmf: ... -->` comment line")
+            }
+            EditError::OutOfRange { index, len } => {
+                write!(f, "turn {} out of range ({} turns)", index, len)
+            }
+            EditError::NoAlternate { turn, index } => {
+                write!(f, "turn {} has no alternate {}", turn, index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+impl Document {
+    /// Append a new turn with the given user message and no reply yet
+    pub fn push_user(
+        &mut self,
+        username: Option<&str>,
+        content: &str,
+    ) -> Result<&mut Turn, EditError> {
+        let user = user_message(username, content)?;
+        self.turns.push(Turn {
+            user,
+            ..Default::default()
+        });
+        Ok(self.turns.last_mut().unwrap())
+    }
+
+    /// Append to the reply of the last turn, starting a turn if there is none
+    ///
+    /// Content is appended as-is, so streamed deltas can be pushed one at a time.
+    pub fn push_assistant(&mut self, content: &str) -> Result<&mut Turn, EditError> {
+        let existing = self.turns.last().map_or("", |turn| turn.assistant.as_str());
+        let assistant = escape_checked(&format!("{}{}", existing, content))?;
+        if self.turns.is_empty() {
+            self.turns.push(Turn::default());
+        }
+        let turn = self.turns.last_mut().unwrap();
+        turn.assistant = assistant;
+        Ok(turn)
+    }
+
+    /// Insert a turn before `index` (or at the end when `index == len`)
+    pub fn insert_turn(&mut self, index: usize, mut turn: Turn) -> Result<(), EditError> {
+        if index > self.turns.len() {
+            return Err(EditError::OutOfRange {
+                index,
+                len: self.turns.len(),
+            });
+        }
+        let user = user_message(turn.user.username.as_deref(), &turn.user.content)?;
+        turn.user.username = user.username;
+        turn.user.content = user.content;
+        turn.assistant = assistant_content(&turn.assistant)?;
+        for alternate in &mut turn.alternates {
+            alternate.content = assistant_content(&alternate.content)?;
+        }
+        self.turns.insert(index, turn);
+        Ok(())
+    }
+
+    /// Remove and return the turn at `index`
+    pub fn remove_turn(&mut self, index: usize) -> Result<Turn, EditError> {
+        self.check_index(index)?;
+        Ok(self.turns.remove(index))
+    }
+
+    /// Keep turns up to and including `index`, dropping everything after it
+    pub fn truncate_after(&mut self, index: usize) -> Result<(), EditError> {
+        self.check_index(index)?;
+        self.turns.truncate(index.saturating_add(1));
+        Ok(())
+    }
+
+    /// Replace the reply of the turn at `index`
+    pub fn replace_assistant(&mut self, index: usize, content: &str) -> Result<(), EditError> {
+        self.check_index(index)?;
+        self.turns[index].assistant = assistant_content(content)?;
+        Ok(())
+    }
+
+    /// Add a candidate reply to the turn at `index`, next to its main reply
+    pub fn push_alternate(
+        &mut self,
+        index: usize,
+        content: &str,
+        meta: Meta,
+    ) -> Result<(), EditError> {
+        self.check_index(index)?;
+        let content = assistant_content(content)?;
+        self.turns[index]
+            .alternates
+            .push(Alternate { content, meta });
+        Ok(())
+    }
+
+    /// Make alternate `alternate` the main reply of the turn at `index`
+    ///
+    /// The previous main reply takes its place among the alternates.
+    pub fn select_alternate(&mut self, index: usize, alternate: usize) -> Result<(), EditError> {
+        self.check_index(index)?;
+        let turn = &mut self.turns[index];
+        let Some(chosen) = turn.alternates.get_mut(alternate) else {
+            return Err(EditError::NoAlternate {
+                turn: index,
+                index: alternate,
+            });
+        };
+        std::mem::swap(&mut turn.assistant, &mut chosen.content);
+        std::mem::swap(&mut turn.meta, &mut chosen.meta);
+        Ok(())
+    }
+
+    /// Keep only the turns for which `keep` returns true
+    pub fn retain(&mut self, keep: impl FnMut(&Turn) -> bool) {
+        self.turns.retain(keep);
+    }
+
+    fn check_index(&self, index: usize) -> Result<(), EditError> {
+        if index < self.turns.len() {
+            Ok(())
+        } else {
+            Err(EditError::OutOfRange {
+                index,
+                len: self.turns.len(),
+            })
+        }
+    }
+}
+
+/// Validate a user message, trimming the blank lines the parser would drop
+fn user_message(username: Option<&str>, content: &str) -> Result<UserMessage, EditError> {
+    if let Some(name) = username {
+        let valid =
+            !name.is_empty() && name.trim() == name && !name.contains(':') && !name.contains('\n');
+        if !valid {
+            return Err(EditError::InvalidUsername(name.to_string()));
+        }
+    }
+
+    let content = content.trim_matches(['\n', '\r']);
+    if username.is_none()
+        && content.starts_with('@')
+        && content.lines().next().unwrap_or("").contains(':')
+    {
+        return Err(EditError::AmbiguousAttribution);
+    }
+    if content
+        .lines()
+        .any(|line| meta::parse_comment(line).is_some())
+    {
+        return Err(EditError::MetadataComment);
+    }
+
+    Ok(UserMessage {
+        username: username.map(str::to_string),
+        content: content.to_string(),
+        ..Default::default()
+    })
+}
+
+/// Validate and escape assistant content, trimming the blank lines the
+/// parser would drop
+fn assistant_content(content: &str) -> Result<String, EditError> {
+    escape_checked(content.trim_matches(['\n', '\r']))
+}
+
+/// Validate and escape assistant content as-is
+fn escape_checked(content: &str) -> Result<String, EditError> {
+    if content
+        .lines()
+        .any(|line| meta::parse_comment(line).is_some())
+    {
+        return Err(EditError::MetadataComment);
+    }
+    Ok(escape_assistant(content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_user_and_assistant() {
+        let mut doc = Document::default();
+        doc.push_user(Some("alice"), "What is 2+2?\n").unwrap();
+        doc.push_assistant("The answer").unwrap();
+        doc.push_assistant(" is 4.\n> quoted").unwrap();
+
+        assert_eq!(doc.turns[0].user.content, "What is 2+2?");
+        assert_eq!(doc.turns[0].assistant, "The answer is 4.\n > quoted");
+        assert_eq!(Document::parse(&doc.to_cmf()), doc);
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut doc = Document::default();
+        assert_eq!(
+            doc.push_user(Some("al:ice"), "Hi").unwrap_err(),
+            EditError::InvalidUsername("al:ice".to_string())
+        );
+        assert_eq!(
+            doc.push_user(None, "@bob: hi").unwrap_err(),
+            EditError::AmbiguousAttribution
+        );
+        assert_eq!(
+            doc.push_assistant("<!-- cmf: label=good -->").unwrap_err(),
+            EditError::MetadataComment
+        );
+        assert!(doc.turns.is_empty());
+        doc.push_user(None, "Hi").unwrap();
+        assert_eq!(
+            doc.replace_assistant(3, "x").unwrap_err(),
+            EditError::OutOfRange { index: 3, len: 1 }
+        );
+    }
+
+    #[test]
+    fn test_structural_edits() {
+        let mut doc = Document::parse("> one\nA\n\n> two\nB\n\n> three\nC");
+        doc.insert_turn(
+            1,
+            Turn {
+                user: UserMessage {
+                    content: "one and a half".to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(doc.remove_turn(2).unwrap().user.content, "two");
+        doc.replace_assistant(0, "Alpha").unwrap();
+        doc.retain(|turn| turn.user.content != "three");
+        assert_eq!(
+            doc.truncate_after(5).unwrap_err(),
+            EditError::OutOfRange { index: 5, len: 2 }
+        );
+        assert!(doc.truncate_after(usize::MAX).is_err());
+
+        let contents: Vec<&str> = doc.turns.iter().map(|t| t.user.content.as_str()).collect();
+        assert_eq!(contents, vec!["one", "one and a half"]);
+        assert_eq!(doc.to_cmf(), "> one\nAlpha\n\n> one and a half");
+
+        doc.truncate_after(0).unwrap();
+        assert_eq!(doc.turns.len(), 1);
+
+        doc.replace_assistant(0, "\nx\n\n").unwrap();
+        assert_eq!(doc.turns[0].assistant, "x");
+        assert_eq!(Document::parse(&doc.to_cmf()), doc);
+    }
+
+    #[test]
+    fn test_alternates() {
+        let mut doc = Document::parse("> Write a haiku\nShort one");
+        let rating = Meta::from([("rating".to_string(), "4".to_string())]);
+        doc.push_alternate(0, "> Longer one", rating).unwrap();
+        assert_eq!(
+            doc.to_cmf(),
+            "> Write a haiku\nShort one\n\n<!-- cmf: alternate=1 rating=4 -->\n > Longer one"
+        );
+        assert_eq!(Document::parse(&doc.to_cmf()), doc);
+
+        doc.select_alternate(0, 0).unwrap();
+        assert_eq!(doc.turns[0].assistant, " > Longer one");
+        assert_eq!(doc.turns[0].meta.get("rating").unwrap(), "4");
+        assert_eq!(doc.turns[0].alternates[0].content, "Short one");
+        assert_eq!(
+            doc.select_alternate(0, 1).unwrap_err(),
+            EditError::NoAlternate { turn: 0, index: 1 }
+        );
+    }
+
+    #[test]
+    fn test_edits_preserve_untouched_spans() {
+        let input = "# Notes\n\n>Hello\n\n\nHi there!\n\n> Second\nReply";
+        let mut doc = Document::parse(input);
+        assert_eq!(doc.to_cmf(), input);
+
+        doc.replace_assistant(1, "New reply").unwrap();
+        doc.push_user(None, "Third").unwrap();
+        assert_eq!(
+            doc.to_cmf(),
+            "# Notes\n\n>Hello\n\n\nHi there!\n\n> Second\nNew reply\n\n> Third"
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! Locked writes to CMF files
+//!
+//! Chat clients treat the file as the conversation, so new content is
+//! appended rather than rewritten: a crash mid-write can at worst leave a
+//! partial reply, and edits other tools made earlier in the file are never
+//! clobbered. Structural edits such as undo go through [`rewrite`], which
+//! swaps in a complete new file so a failed write leaves the old one intact.
+//! Every write holds an exclusive advisory lock on the file and first checks
+//! that the file is in the state the caller expects.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{Document, EditError, Turn};
+
+/// What the caller believes the file looks like before appending
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expect {
+    /// Append to whatever the file currently holds
+    Any,
+    /// The file must hold exactly this many turns
+    Turns(usize),
+    /// The file must be exactly this many bytes long, such as the length
+    /// returned by the previous append
+    Len(u64),
+}
+
+/// An error raised while appending to a file
+#[derive(Debug)]
+pub enum FileError {
+    Io(io::Error),
+    /// The content to append is not valid CMF
+    Edit(EditError),
+    /// The file changed since the caller last looked at it
+    Unexpected {
+        expected: Expect,
+        found: String,
+    },
+    /// There is no turn to append an assistant reply to
+    NoTurn,
+}
+
+impl std::fmt::Display for FileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileError::Io(e) => write!(f, "{}", e),
+            FileError::Edit(e) => write!(f, "{}", e),
+            FileError::Unexpected { expected, found } => {
+                write!(f, "file changed: expected {:?}, found {}", expected, found)
+            }
+            FileError::NoTurn => write!(f, "no user turn to reply to"),
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
+impl From<io::Error> for FileError {
+    fn from(e: io::Error) -> Self {
+        FileError::Io(e)
+    }
+}
+
+impl From<EditError> for FileError {
+    fn from(e: EditError) -> Self {
+        FileError::Edit(e)
+    }
+}
+
+/// Append a complete turn, creating the file if needed
+///
+/// Returns the new length of the file.
+pub fn append_turn(path: &Path, turn: &Turn, expect: Expect) -> Result<u64, FileError> {
+    // Validate and escape through the editing API
+    let mut staged = Document::default();
+    staged.insert_turn(0, turn.clone())?;
+    let text = staged.turns[0].to_cmf();
+
+    let mut file = open_locked(
+        path,
+        OpenOptions::new().read(true).append(true).create(true),
+    )?;
+    let content = read_locked(&mut file)?;
+    check(&content, expect)?;
+
+    // A user line must follow a blank line (or start the file)
+    let separator = if content.is_empty() || content.ends_with("\n\n") {
+        ""
+    } else if content.ends_with('\n') {
+        "\n"
+    } else {
+        "\n\n"
+    };
+
+    write_locked(&mut file, &format!("{}{}\n", separator, text))
+}
+
+/// Append a chunk of assistant text to the reply of the last turn
+///
+/// Chunks are written as-is, so streamed deltas can be appended one at a
+/// time; `>` at the start of a line is escaped. Returns the new length of
+/// the file, suitable for `Expect::Len` on the next call.
+///
+/// With `Expect::Len` the caller already knows the file holds a turn, so
+/// only its last line is read; this keeps streaming a long reply linear.
+pub fn append_assistant_delta(path: &Path, delta: &str, expect: Expect) -> Result<u64, FileError> {
+    let mut file = open_locked(path, OpenOptions::new().read(true).append(true))?;
+    // Whether the reply has yet to start on a line of its own, and whether
+    // the file ends at the start of a line
+    let (starting, ends_with_newline) = match expect {
+        Expect::Len(len) => {
+            let found = file.metadata()?.len();
+            if found != len {
+                return Err(FileError::Unexpected {
+                    expected: expect,
+                    found: format!("{} bytes", found),
+                });
+            }
+            if len == 0 {
+                return Err(FileError::NoTurn);
+            }
+            let line = read_last_line(&mut file, len)?;
+            // A user line or a metadata line the reply has not followed yet
+            let starting = line.starts_with('>') || line.starts_with("<!-- cmf:");
+            (starting, line.is_empty())
+        }
+        _ => {
+            let content = read_locked(&mut file)?;
+            check(&content, expect)?;
+            let doc = Document::parse(&content);
+            let last = doc.turns.last().ok_or(FileError::NoTurn)?;
+            let ends_with_newline = content.ends_with('\n');
+            (
+                last.assistant.is_empty() && !ends_with_newline,
+                ends_with_newline,
+            )
+        }
+    };
+
+    let mut text = String::new();
+    // Starting a reply: move off the end of the user block
+    if starting {
+        text.push('\n');
+    }
+    let mut at_line_start = ends_with_newline || starting;
+    for c in delta.chars() {
+        if at_line_start && c == '>' {
+            text.push(' ');
+        }
+        text.push(c);
+        at_line_start = c == '\n';
+    }
+
+    write_locked(&mut file, &text)
+}
+
+/// Replace the whole file with `doc`, for edits that are not appends
+///
+/// The new content goes to a temporary file next to `path`, which is synced
+/// and then renamed over it while the lock is held, so readers and
+/// appenders see either the old or the new content, and a failed write
+/// leaves the old content in place. Returns the new length.
+pub fn rewrite(path: &Path, doc: &Document, expect: Expect) -> Result<u64, FileError> {
+    let mut file = open_locked(path, OpenOptions::new().read(true).write(true).create(true))?;
+    let content = read_locked(&mut file)?;
+    check(&content, expect)?;
+
+    let mut text = doc.to_cmf();
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    let temp = temp_path(path);
+    let written = (|| {
+        let mut staged = File::create(&temp)?;
+        staged.set_permissions(file.metadata()?.permissions())?;
+        staged.write_all(text.as_bytes())?;
+        staged.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(e.into());
+    }
+    file.unlock()?;
+    Ok(text.len() as u64)
+}
+
+/// Open and lock `path`, making sure the lock is on the file `path` still
+/// names rather than one a [`rewrite`] replaced while we waited
+fn open_locked(path: &Path, options: &OpenOptions) -> io::Result<File> {
+    loop {
+        let file = options.open(path)?;
+        file.lock()?;
+        if is_current(&file, path)? {
+            return Ok(file);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_current(file: &File, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (open, named) = (file.metadata()?, fs::metadata(path)?);
+    Ok(open.dev() == named.dev() && open.ino() == named.ino())
+}
+
+#[cfg(not(unix))]
+fn is_current(_file: &File, _path: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// A hidden name next to `path` for staging its new content
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
+
+fn read_locked(file: &mut File) -> io::Result<String> {
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// The text after the last newline of a file `len` bytes long, read
+/// backwards in blocks so a long file is not read in full
+fn read_last_line(file: &mut File, len: u64) -> io::Result<String> {
+    const BLOCK: u64 = 4096;
+    let mut line = Vec::new();
+    let mut end = len;
+    while end > 0 {
+        let start = end.saturating_sub(BLOCK);
+        let mut block = vec![0; (end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut block)?;
+        let newline = block.iter().rposition(|&b| b == b'\n');
+        block.extend_from_slice(&line);
+        line = block;
+        if let Some(i) = newline {
+            line.drain(..=i);
+            break;
+        }
+        end = start;
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+fn write_locked(file: &mut File, text: &str) -> Result<u64, FileError> {
+    file.write_all(text.as_bytes())?;
+    file.flush()?;
+    let len = file.metadata()?.len();
+    file.unlock()?;
+    Ok(len)
+}
+
+fn check(content: &str, expect: Expect) -> Result<(), FileError> {
+    let found = match expect {
+        Expect::Any => return Ok(()),
+        Expect::Len(len) if content.len() as u64 == len => return Ok(()),
+        Expect::Len(_) => format!("{} bytes", content.len()),
+        Expect::Turns(turns) => {
+            let found = Document::parse(content).turns.len();
+            if found == turns {
+                return Ok(());
+            }
+            format!("{} turns", found)
+        }
+    };
+    Err(FileError::Unexpected {
+        expected: expect,
+        found,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UserMessage;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_file(name: &str, content: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("cmf-file-{}-{}.cmf", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn user_turn(content: &str) -> Turn {
+        Turn {
+            user: UserMessage {
+                content: content.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_append_turn_and_stream_reply() {
+        let path = temp_file("stream", "> Hello\nHi!");
+
+        let len = append_turn(&path, &user_turn("Quote something"), Expect::Turns(1)).unwrap();
+        let len = append_assistant_delta(&path, "Here:", Expect::Len(len)).unwrap();
+        let len = append_assistant_delta(&path, "\n", Expect::Len(len)).unwrap();
+        append_assistant_delta(&path, "> To be", Expect::Len(len)).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            "> Hello\nHi!\n\n> Quote something\nHere:\n > To be"
+        );
+        let doc = Document::parse(&content);
+        assert_eq!(doc.turns.len(), 2);
+        assert_eq!(doc.turns[1].assistant, "Here:\n > To be");
+        assert!(Document::check(&content).is_empty());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_stream_reads_only_the_last_line() {
+        let long = "word ".repeat(2000);
+        let cases = [
+            ("> Hi", "> Hi\nHello"),
+            (
+                "> Hi\n<!-- cmf: model=x -->",
+                "> Hi\n<!-- cmf: model=x -->\nHello",
+            ),
+            ("> Hi\n", "> Hi\nHello"),
+        ];
+        for (i, (before, after)) in cases.into_iter().enumerate() {
+            let path = temp_file(&format!("tail{}", i), before);
+            append_assistant_delta(&path, "Hello", Expect::Len(before.len() as u64)).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), after);
+            fs::remove_file(path).unwrap();
+        }
+
+        // A last line longer than one block
+        let before = format!("> Hi\n{}", long);
+        let path = temp_file("tail-long", &before);
+        let len = append_assistant_delta(&path, "\n>", Expect::Len(before.len() as u64)).unwrap();
+        append_assistant_delta(&path, ">", Expect::Len(len)).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content, format!("{}\n >>", before));
+        assert_eq!(Document::parse(&content).turns.len(), 1);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_unexpected_state() {
+        let path = temp_file("unexpected", "> Hello\n");
+
+        assert!(matches!(
+            append_turn(&path, &user_turn("Again"), Expect::Turns(2)),
+            Err(FileError::Unexpected { .. })
+        ));
+        assert!(matches!(
+            append_assistant_delta(&path, "Hi", Expect::Len(3)),
+            Err(FileError::Unexpected { .. })
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "> Hello\n");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let path = temp_file("rewrite", "> One\nA\n\n> Two\nB\n");
+        let mut doc = Document::parse(&fs::read_to_string(&path).unwrap());
+        doc.remove_turn(1).unwrap();
+
+        assert!(matches!(
+            rewrite(&path, &doc, Expect::Turns(1)),
+            Err(FileError::Unexpected { .. })
+        ));
+        let len = rewrite(&path, &doc, Expect::Turns(2)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "> One\nA\n");
+        append_turn(&path, &user_turn("Three"), Expect::Len(len)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "> One\nA\n\n> Three\n");
+        assert!(!temp_path(&path).exists());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reply_needs_a_turn() {
+        let path = temp_file("noturn", "");
+        assert!(matches!(
+            append_assistant_delta(&path, "Hi", Expect::Any),
+            Err(FileError::NoTurn)
+        ));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+//! Searching conversations turn by turn
+//!
+//! Unlike a plain text search, a [`Search`] knows which turn and which side
+//! of it every line belongs to, so it can be limited to user questions,
+//! assistant replies or one user's messages. Metadata comments are not
+//! searched.
+
+use colored::*;
+use regex::{Regex, RegexBuilder};
+
+use crate::{meta, Document};
+
+/// Which side of a turn a line belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+/// A line of a conversation file, placed in its turn
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line<'a> {
+    /// Line number in the file, from 1
+    pub number: usize,
+    /// Index of the turn, or `None` for the preamble
+    pub turn: Option<usize>,
+    pub role: Role,
+    /// The line as written, `>` included
+    pub text: &'a str,
+}
+
+/// Split `input` into lines, each placed in its turn the way
+/// [`Document::parse`] reads them
+pub fn lines(input: &str) -> Vec<Line<'_>> {
+    let mut lines = Vec::new();
+    let mut turn: Option<usize> = None;
+    let mut in_user_block = false;
+    for (i, text) in input.lines().enumerate() {
+        let is_user_line = text.starts_with('>');
+        if is_user_line && !in_user_block {
+            turn = Some(turn.map_or(0, |turn| turn + 1));
+        }
+        in_user_block = is_user_line;
+        lines.push(Line {
+            number: i + 1,
+            turn,
+            role: if is_user_line {
+                Role::User
+            } else {
+                Role::Assistant
+            },
+            text,
+        });
+    }
+    lines
+}
+
+/// One matching line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    /// Index of the turn
+    pub turn: usize,
+    pub line: Line<'a>,
+}
+
+/// A pattern, optionally limited to one side of the conversation
+#[derive(Debug, Clone)]
+pub struct Search {
+    pub regex: Regex,
+    pub role: Option<Role>,
+    /// Only messages from this user (and the replies to them, unless `role`
+    /// is `User`)
+    pub user: Option<String>,
+}
+
+impl Search {
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Self, regex::Error> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build()?;
+        Ok(Search {
+            regex,
+            role: None,
+            user: None,
+        })
+    }
+
+    /// The matching lines of a conversation file
+    pub fn find<'a>(&self, input: &'a str) -> Vec<Match<'a>> {
+        let doc = Document::parse(input);
+        lines(input)
+            .into_iter()
+            .filter_map(|line| {
+                let turn = line.turn?;
+                self.matches(&doc, &line).then_some(Match { turn, line })
+            })
+            .collect()
+    }
+
+    /// Format matches as `path:turn:line:text`, counting turns from 1
+    ///
+    /// With `context`, the rest of each matching turn is shown too, as
+    /// `path-turn-line-text`, and turns are separated by `--`.
+    pub fn format(
+        &self,
+        path: &str,
+        input: &str,
+        matches: &[Match],
+        context: bool,
+        use_colors: bool,
+    ) -> String {
+        let mut turns: Vec<usize> = matches.iter().map(|m| m.turn).collect();
+        turns.dedup();
+
+        let mut output = Vec::new();
+        let lines = lines(input);
+        for turn in turns {
+            if context && !output.is_empty() {
+                output.push("--".to_string());
+            }
+            let mut shown: Vec<&Line> = lines
+                .iter()
+                .filter(|line| line.turn == Some(turn))
+                .filter(|line| context || matches.iter().any(|m| m.line.number == line.number))
+                .collect();
+            while shown.last().is_some_and(|line| line.text.trim().is_empty()) {
+                shown.pop();
+            }
+
+            for line in shown {
+                let matched = matches.iter().any(|m| m.line.number == line.number);
+                let separator = if matched { ":" } else { "-" };
+                let (turn_number, number) = ((turn + 1).to_string(), line.number.to_string());
+                let (fields, text) = if use_colors {
+                    let fields = [
+                        path.magenta().to_string(),
+                        turn_number.cyan().to_string(),
+                        number.green().to_string(),
+                    ];
+                    let text = if matched {
+                        self.highlight(line.text)
+                    } else {
+                        line.text.to_string()
+                    };
+                    (fields, text)
+                } else {
+                    (
+                        [path.to_string(), turn_number, number],
+                        line.text.to_string(),
+                    )
+                };
+                let prefix = fields.join(separator);
+                output.push(format!("{}{}{}", prefix, separator, text));
+            }
+        }
+        output.join("\n")
+    }
+
+    fn highlight(&self, text: &str) -> String {
+        self.regex
+            .replace_all(text, |caps: &regex::Captures| {
+                caps[0].red().bold().to_string()
+            })
+            .into_owned()
+    }
+
+    fn matches(&self, doc: &Document, line: &Line) -> bool {
+        let Some(turn) = line.turn.and_then(|turn| doc.turns.get(turn)) else {
+            return false;
+        };
+        if self.role.is_some_and(|role| role != line.role) {
+            return false;
+        }
+        if let Some(user) = &self.user {
+            if turn.user.username.as_deref() != Some(user.as_str()) {
+                return false;
+            }
+        }
+
+        let text = match line.role {
+            Role::User => {
+                let text = line.text.strip_prefix('>').unwrap_or(line.text);
+                text.strip_prefix(' ').unwrap_or(text)
+            }
+            Role::Assistant => line.text,
+        };
+        meta::parse_comment(text).is_none() && self.regex.is_match(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAT: &str = "---\ntitle: Deploys\n---\n\n> @alice: How do I deploy?\nRun `deploy.sh`.\n<!-- cmf: label=deploy -->\n\n> @bob: Can I deploy on Friday?\n> Asking for a friend\nPlease don't deploy on Fridays.";
+
+    fn found(search: &Search) -> Vec<(usize, usize)> {
+        search
+            .find(CHAT)
+            .iter()
+            .map(|m| (m.turn, m.line.number))
+            .collect()
+    }
+
+    #[test]
+    fn test_lines() {
+        let lines = lines(CHAT);
+        assert_eq!(lines[0].turn, None);
+        assert_eq!(lines[4].turn, Some(0));
+        assert_eq!(lines[4].role, Role::User);
+        assert_eq!(lines[5].role, Role::Assistant);
+        assert_eq!(lines[9].turn, Some(1));
+        assert_eq!(lines[9].text, "> Asking for a friend");
+    }
+
+    #[test]
+    fn test_search() {
+        let mut search = Search::new("deploy", false).unwrap();
+        // The title and the annotation are not searched
+        assert_eq!(found(&search), vec![(0, 5), (0, 6), (1, 9), (1, 11)]);
+
+        search.role = Some(Role::Assistant);
+        assert_eq!(found(&search), vec![(0, 6), (1, 11)]);
+
+        search.role = None;
+        search.user = Some("bob".to_string());
+        assert_eq!(found(&search), vec![(1, 9), (1, 11)]);
+
+        let search = Search::new(r"^please\b", true).unwrap();
+        assert_eq!(found(&search), vec![(1, 11)]);
+    }
+
+    #[test]
+    fn test_format() {
+        let search = Search::new("Friday", false).unwrap();
+        let matches = search.find(CHAT);
+        assert_eq!(
+            search.format("chat.cmf", CHAT, &matches, false, false),
+            "chat.cmf:2:9:> @bob: Can I deploy on Friday?\n\
+             chat.cmf:2:11:Please don't deploy on Fridays."
+        );
+        assert_eq!(
+            search.format("chat.cmf", CHAT, &matches, true, false),
+            "chat.cmf:2:9:> @bob: Can I deploy on Friday?\n\
+             chat.cmf-2-10-> Asking for a friend\n\
+             chat.cmf:2:11:Please don't deploy on Fridays."
+        );
+
+        let search = Search::new("deploy", false).unwrap();
+        let matches = search.find(CHAT);
+        let output = search.format("chat.cmf", CHAT, &matches, true, false);
+        assert!(output.contains("chat.cmf-1-7-<!-- cmf: label=deploy -->\n--\nchat.cmf:2:9:"));
+    }
+}
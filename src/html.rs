@@ -0,0 +1,210 @@
+//! Standalone HTML export with a chat-bubble layout
+//!
+//! Produces a single self-contained page: CSS is embedded, there are no
+//! external assets, and it follows the reader's light/dark preference.
+//! Raw HTML inside messages is escaped rather than passed through, and links
+//! and images may only point at web, mail or relative addresses.
+
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+use crate::Document;
+
+/// Embedded stylesheet shared by every generated page
+pub const STYLE: &str = r#"
+:root {
+  --bg: #ffffff; --fg: #1f2328; --muted: #656d76; --border: #d0d7de;
+  --user-bg: #dbeafe; --user-fg: #0b2447; --code-bg: #f6f8fa; --link: #0969da;
+}
+@media (prefers-color-scheme: dark) {
+  :root {
+    --bg: #0d1117; --fg: #e6edf3; --muted: #8d96a0; --border: #30363d;
+    --user-bg: #1f3a5f; --user-fg: #e6edf3; --code-bg: #161b22; --link: #4493f8;
+  }
+}
+* { box-sizing: border-box; }
+body {
+  margin: 0; background: var(--bg); color: var(--fg);
+  font: 16px/1.6 -apple-system, BlinkMacSystemFont, "Segoe UI", Helvetica, Arial, sans-serif;
+}
+main { max-width: 48rem; margin: 0 auto; padding: 2rem 1rem 4rem; }
+h1.title { font-size: 1.5rem; margin: 0 0 2rem; }
+a { color: var(--link); }
+.turn { position: relative; margin: 0 0 2rem; }
+.anchor { position: absolute; left: -2.5rem; top: 0.5rem; color: var(--muted);
+  font-size: 0.8rem; text-decoration: none; opacity: 0; }
+.turn:hover .anchor, .anchor:focus { opacity: 1; }
+.user { display: flex; flex-direction: column; align-items: flex-end; margin-bottom: 1rem; }
+.username { font-size: 0.8rem; color: var(--muted); margin: 0 0.75rem 0.25rem; }
+.bubble { background: var(--user-bg); color: var(--user-fg); padding: 0.5rem 1rem;
+  border-radius: 1.25rem 1.25rem 0.25rem 1.25rem; max-width: 85%; overflow-wrap: anywhere; }
+.bubble > :first-child, .assistant > :first-child { margin-top: 0; }
+.bubble > :last-child, .assistant > :last-child { margin-bottom: 0; }
+.assistant { overflow-wrap: anywhere; }
+pre { background: var(--code-bg); border: 1px solid var(--border); border-radius: 6px;
+  padding: 0.75rem 1rem; overflow-x: auto; }
+code { font: 0.875em ui-monospace, SFMono-Regular, Menlo, Consolas, monospace; }
+:not(pre) > code { background: var(--code-bg); padding: 0.1em 0.3em; border-radius: 4px; }
+blockquote { margin: 0; padding: 0 1rem; border-left: 0.25rem solid var(--border); color: var(--muted); }
+table { border-collapse: collapse; display: block; overflow-x: auto; }
+th, td { border: 1px solid var(--border); padding: 0.3rem 0.75rem; }
+img { max-width: 100%; }
+nav { margin: -1rem 0 1rem; font-size: 0.9rem; }
+.meta { color: var(--muted); font-size: 0.85rem; margin: 0.25rem 0 1.5rem; }
+.tag { display: inline-block; padding: 0 0.5rem; border: 1px solid var(--border);
+  border-radius: 1rem; font-size: 0.8rem; text-decoration: none; }
+ul.conversations { list-style: none; padding: 0; }
+ul.conversations li { margin-bottom: 1rem; }
+ul.conversations .meta { margin: 0; }
+input.search { width: 100%; padding: 0.5rem 0.75rem; font: inherit; color: var(--fg);
+  background: var(--bg); border: 1px solid var(--border); border-radius: 6px; }
+"#;
+
+impl Document {
+    /// Render the conversation as a self-contained HTML page
+    pub fn to_html(&self, title: &str) -> String {
+        page(title, &render_turns(self))
+    }
+}
+
+/// Wrap body markup in a complete page with the embedded stylesheet
+pub fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!doctype html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <meta name=\"generator\" content=\"cmf\">\n<title>{title}</title>\n\
+         <style>{style}</style>\n</head>\n<body>\n<main>\n<h1 class=\"title\">{title}</h1>\n\
+         {body}</main>\n</body>\n</html>\n",
+        title = escape(title),
+        style = STYLE,
+        body = body,
+    )
+}
+
+/// Render each turn as a user bubble followed by the assistant reply
+pub fn render_turns(doc: &Document) -> String {
+    let mut output = String::new();
+
+    for (i, turn) in doc.turns.iter().enumerate() {
+        let id = format!("turn-{}", i + 1);
+        output.push_str(&format!("<section class=\"turn\" id=\"{}\">\n", id));
+        output.push_str(&format!(
+            "<a class=\"anchor\" href=\"#{}\" aria-label=\"Link to turn {}\">#{}</a>\n",
+            id,
+            i + 1,
+            i + 1
+        ));
+
+        output.push_str("<div class=\"user\">\n");
+        if let Some(ref username) = turn.user.username {
+            output.push_str(&format!(
+                "<div class=\"username\">@{}</div>\n",
+                escape(username)
+            ));
+        }
+        output.push_str("<div class=\"bubble\">\n");
+        output.push_str(&markdown_to_html(&turn.user.content));
+        output.push_str("</div>\n</div>\n");
+
+        if !turn.assistant.is_empty() {
+            output.push_str("<div class=\"assistant\">\n");
+            output.push_str(&markdown_to_html(&turn.assistant));
+            output.push_str("</div>\n");
+        }
+
+        output.push_str("</section>\n");
+    }
+
+    output
+}
+
+/// Render markdown to HTML, escaping any raw HTML it contains and pointing
+/// links with other schemes, such as `javascript:`, at `#`
+pub fn markdown_to_html(markdown: &str) -> String {
+    let parser = Parser::new_ext(markdown, Options::all()).map(|event| match event {
+        Event::Html(raw) => Event::Text(raw),
+        Event::Start(Tag::Link(kind, url, title)) => {
+            Event::Start(Tag::Link(kind, safe_url(url), title))
+        }
+        Event::Start(Tag::Image(kind, url, title)) => {
+            Event::Start(Tag::Image(kind, safe_url(url), title))
+        }
+        other => other,
+    });
+    let mut output = String::new();
+    html::push_html(&mut output, parser);
+    output
+}
+
+/// `url` if it is relative or uses http(s) or mailto, `#` otherwise
+fn safe_url(url: CowStr) -> CowStr {
+    // Browsers ignore whitespace and control characters inside a scheme
+    let compact: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    let scheme = compact
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| !scheme.contains(['/', '?', '#']));
+    match scheme {
+        None | Some("http" | "https" | "mailto") => url,
+        Some(_) => CowStr::Borrowed("#"),
+    }
+}
+
+/// Escape text for use in HTML content and attribute values
+pub fn escape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_html() {
+        let doc = Document::parse("> @alice: Hi <b>there</b>\n**Hello** and `code`\n\n> Thanks");
+        let html = doc.to_html("Greetings & more");
+
+        assert!(html.starts_with("<!doctype html>"));
+        assert!(html.contains("<title>Greetings &amp; more</title>"));
+        assert!(html.contains("id=\"turn-1\""));
+        assert!(html.contains("href=\"#turn-2\""));
+        assert!(html.contains("@alice"));
+        assert!(html.contains("&lt;b&gt;there&lt;/b&gt;"));
+        assert!(html.contains("<strong>Hello</strong>"));
+        assert!(html.contains("prefers-color-scheme: dark"));
+        assert!(!html.contains("src=\"http"));
+        assert!(!html.contains("<link"));
+    }
+
+    #[test]
+    fn test_unsafe_links() {
+        let html = markdown_to_html(
+            "[a](javascript:alert(1)) [b](JavaScript:x) ![c](data:image/png;base64,AA) \
+             [d](https://example.com) [e](mailto:a@example.com) [f](notes/a.html) \
+             [g](#turn-2) [h](?q=a:b)",
+        );
+        assert!(!html.to_lowercase().contains("javascript"));
+        assert!(!html.contains("data:"));
+        assert!(html.contains("href=\"#\">a</a>"));
+        assert!(html.contains("src=\"#\""));
+        assert!(html.contains("href=\"https://example.com\""));
+        assert!(html.contains("href=\"mailto:a@example.com\""));
+        assert!(html.contains("href=\"notes/a.html\""));
+        assert!(html.contains("href=\"#turn-2\""));
+        assert!(html.contains("href=\"?q=a:b\""));
+    }
+}
@@ -0,0 +1,144 @@
+//! Importers for conversations exported from other chat tools
+//!
+//! Each importer turns an export into a flat list of [`Message`]s, which
+//! [`build_document`] then maps onto CMF turns.
+
+pub mod subtitles;
+pub mod telegram;
+pub mod transcript;
+pub mod whatsapp;
+
+use crate::{Document, Meta, Turn, UserMessage};
+
+/// A single message from an export, before it is mapped onto turns
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub sender: String,
+    pub text: String,
+    pub meta: Meta,
+}
+
+/// An error raised while reading an export
+#[derive(Debug)]
+pub enum ImportError {
+    /// The export is not valid JSON or does not match the expected shape
+    Json(serde_json::Error),
+    /// The export could be read but is not in a recognised layout
+    Format(String),
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Json(e) => write!(f, "invalid JSON: {}", e),
+            ImportError::Format(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<serde_json::Error> for ImportError {
+    fn from(e: serde_json::Error) -> Self {
+        ImportError::Json(e)
+    }
+}
+
+/// Map messages onto turns
+///
+/// Messages from `assistant` become assistant replies, with consecutive ones
+/// merged into the same reply. Everyone else gets an attributed user block.
+pub fn build_document(messages: Vec<Message>, assistant: Option<&str>) -> Document {
+    let mut turns: Vec<Turn> = Vec::new();
+
+    for message in messages {
+        if assistant == Some(message.sender.as_str()) {
+            if turns.is_empty() {
+                // Assistant spoke first: hang the reply off an empty user block
+                turns.push(Turn::default());
+            }
+            let turn = turns.last_mut().unwrap();
+            if turn.assistant.is_empty() {
+                turn.assistant = message.text;
+                turn.meta = message.meta;
+            } else {
+                turn.assistant.push_str("\n\n");
+                turn.assistant.push_str(&message.text);
+            }
+        } else {
+            turns.push(Turn {
+                user: UserMessage {
+                    username: Some(sanitize_username(&message.sender)),
+                    content: message.text,
+                    meta: message.meta,
+                },
+                ..Default::default()
+            });
+        }
+    }
+
+    Document {
+        turns,
+        ..Default::default()
+    }
+}
+
+/// Make a display name usable in a `> @username:` prefix
+fn sanitize_username(name: &str) -> String {
+    let name: String = name.chars().filter(|c| *c != ':').collect();
+    let name = name.trim();
+    if name.is_empty() {
+        "unknown".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Format a media reference as a markdown link
+fn media_link(label: &str, path: &str) -> String {
+    if path.contains(char::is_whitespace) {
+        format!("[{}](<{}>)", label, path)
+    } else {
+        format!("[{}]({})", label, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(sender: &str, text: &str) -> Message {
+        Message {
+            sender: sender.to_string(),
+            text: text.to_string(),
+            meta: Meta::new(),
+        }
+    }
+
+    #[test]
+    fn test_build_document_all_users() {
+        let doc = build_document(vec![message("alice", "Hi"), message("bob", "Hello")], None);
+        assert_eq!(doc.turns.len(), 2);
+        assert_eq!(doc.turns[1].user.username, Some("bob".to_string()));
+        assert_eq!(Document::parse(&doc.to_cmf()), doc);
+    }
+
+    #[test]
+    fn test_build_document_with_assistant() {
+        let messages = vec![
+            message("support", "Welcome!"),
+            message("alice", "My order is late"),
+            message("support", "Sorry to hear that."),
+            message("support", "Let me check."),
+        ];
+        let doc = build_document(messages, Some("support"));
+        assert_eq!(doc.turns.len(), 2);
+        assert_eq!(doc.turns[0].user.content, "");
+        assert_eq!(doc.turns[0].assistant, "Welcome!");
+        assert_eq!(
+            doc.turns[1].assistant,
+            "Sorry to hear that.\n\nLet me check."
+        );
+        assert_eq!(Document::parse(&doc.to_cmf()), doc);
+    }
+}
@@ -0,0 +1,263 @@
+//! WebVTT and SRT transcript importer
+//!
+//! Speakers come from WebVTT voice tags (`<v Alice>`) or diarization prefixes
+//! (`SPEAKER_00:`, `[Speaker 1]`). Consecutive cues from the same speaker are
+//! merged into one message, and cue timings are kept as `start`/`end` metadata.
+//! A cue without a speaker continues the previous one.
+
+use regex::Regex;
+
+use super::{build_document, ImportError, Message};
+use crate::{Document, Meta};
+
+/// A single subtitle cue
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start: String,
+    pub end: String,
+    pub speaker: Option<String>,
+    pub text: String,
+}
+
+/// Parse a WebVTT transcript; cues from `assistant` become assistant replies
+pub fn parse_vtt(input: &str, assistant: Option<&str>) -> Result<Document, ImportError> {
+    Ok(build_document(
+        merge_cues(parse_vtt_cues(input)?),
+        assistant,
+    ))
+}
+
+/// Parse an SRT transcript; cues from `assistant` become assistant replies
+pub fn parse_srt(input: &str, assistant: Option<&str>) -> Result<Document, ImportError> {
+    Ok(build_document(
+        merge_cues(parse_srt_cues(input)?),
+        assistant,
+    ))
+}
+
+/// Parse the cues of a WebVTT file
+pub fn parse_vtt_cues(input: &str) -> Result<Vec<Cue>, ImportError> {
+    let input = input.trim_start_matches('\u{feff}');
+    if !input.starts_with("WEBVTT") {
+        return Err(ImportError::Format("missing WEBVTT header".to_string()));
+    }
+    let cues = parse_cues(input);
+    if cues.is_empty() {
+        return Err(ImportError::Format("no VTT cues found".to_string()));
+    }
+    Ok(cues)
+}
+
+/// Parse the cues of an SRT file
+pub fn parse_srt_cues(input: &str) -> Result<Vec<Cue>, ImportError> {
+    let cues = parse_cues(input.trim_start_matches('\u{feff}'));
+    if cues.is_empty() {
+        return Err(ImportError::Format("no SRT cues found".to_string()));
+    }
+    Ok(cues)
+}
+
+/// Both formats are blank-line separated blocks with a `-->` timing line
+fn parse_cues(input: &str) -> Vec<Cue> {
+    let voice = Regex::new(r"<v(?:\.[\w.-]+)?\s+([^>]+)>").unwrap();
+    // Only labels diarization tools write, so captions like `NOTE: ...`,
+    // `[Music]` or `[laughs] yes` keep their text
+    let prefix =
+        Regex::new(r"^(?:\[(SPEAKER_\d+|Speaker \d+)\]:?|(SPEAKER_\d+|Speaker \d+):)\s*").unwrap();
+    let tags = Regex::new(r"<[^>]*>").unwrap();
+
+    let normalized = input.replace("\r\n", "\n");
+    let mut cues = Vec::new();
+
+    for block in normalized.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let Some(timing) = lines.next() else {
+            // Header, NOTE, STYLE and REGION blocks have no timing line
+            continue;
+        };
+        let Some((start, end)) = parse_timing(timing) else {
+            continue;
+        };
+
+        let mut speaker = None;
+        let mut text_lines = Vec::new();
+        for line in lines {
+            let mut line = line.to_string();
+            if let Some(caps) = voice.captures(&line) {
+                speaker.get_or_insert_with(|| caps[1].trim().to_string());
+            }
+            line = tags.replace_all(&line, "").to_string();
+            if let Some(caps) = prefix.captures(&line) {
+                let label = caps
+                    .get(1)
+                    .or(caps.get(2))
+                    .unwrap()
+                    .as_str()
+                    .trim()
+                    .to_string();
+                speaker.get_or_insert(label);
+                line = line[caps.get(0).unwrap().end()..].to_string();
+            }
+            let line = decode_entities(line.trim());
+            if !line.is_empty() {
+                text_lines.push(line);
+            }
+        }
+
+        if !text_lines.is_empty() {
+            cues.push(Cue {
+                start,
+                end,
+                speaker,
+                text: text_lines.join(" "),
+            });
+        }
+    }
+
+    cues
+}
+
+/// Parse `00:01.000 --> 00:00:04,000 align:start` into normalised timestamps
+fn parse_timing(line: &str) -> Option<(String, String)> {
+    let (start, rest) = line.split_once("-->")?;
+    let end = rest.split_whitespace().next()?;
+    Some((
+        normalize_timestamp(start.trim())?,
+        normalize_timestamp(end)?,
+    ))
+}
+
+/// Normalise `MM:SS.mmm`, `HH:MM:SS.mmm` and `HH:MM:SS,mmm` to `HH:MM:SS.mmm`
+fn normalize_timestamp(timestamp: &str) -> Option<String> {
+    let (clock, millis) = timestamp.split_once(['.', ',']).unwrap_or((timestamp, "0"));
+    let parts: Vec<u32> = clock
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [h, m, s] => (h, m, s),
+        [m, s] => (0, m, s),
+        _ => return None,
+    };
+    let millis: u32 = format!("{:0<3}", millis).get(..3)?.parse().ok()?;
+    Some(format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        hours, minutes, seconds, millis
+    ))
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Merge consecutive cues by the same speaker into messages
+pub fn merge_cues(cues: Vec<Cue>) -> Vec<Message> {
+    let mut messages: Vec<Message> = Vec::new();
+    let mut current_speaker: Option<String> = None;
+
+    for cue in cues {
+        let speaker = cue.speaker.or_else(|| current_speaker.clone());
+        let continues = speaker.is_some() && speaker == current_speaker;
+
+        match messages.last_mut() {
+            Some(message) if continues || (speaker.is_none() && current_speaker.is_none()) => {
+                message.text.push(' ');
+                message.text.push_str(&cue.text);
+                message.meta.insert("end".to_string(), cue.end);
+            }
+            _ => {
+                let mut meta = Meta::new();
+                meta.insert("start".to_string(), cue.start);
+                meta.insert("end".to_string(), cue.end);
+                messages.push(Message {
+                    sender: speaker.clone().unwrap_or_else(|| "unknown".to_string()),
+                    text: cue.text,
+                    meta,
+                });
+            }
+        }
+        current_speaker = speaker;
+    }
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vtt_voice_tags() {
+        let input = "WEBVTT\n\nNOTE recorded interview\n\n\
+                     1\n00:00:01.000 --> 00:00:04.000\n<v Interviewer>How do you deploy?</v>\n\n\
+                     00:00:05.000 --> 00:00:07.500\n<v.loud Dana>We use <i>make</i>\nand a script.</v>\n\n\
+                     00:07.500 --> 00:00:09.000 align:start\n<v Dana>It works.</v>\n";
+
+        let cues = parse_vtt_cues(input).unwrap();
+        assert_eq!(cues.len(), 3);
+        assert_eq!(cues[1].speaker, Some("Dana".to_string()));
+        assert_eq!(cues[1].text, "We use make and a script.");
+        assert_eq!(cues[2].start, "00:00:07.500");
+
+        let doc = parse_vtt(input, Some("Interviewer")).unwrap();
+        assert_eq!(doc.turns.len(), 2);
+        assert_eq!(doc.turns[0].assistant, "How do you deploy?");
+        assert_eq!(doc.turns[0].meta.get("start").unwrap(), "00:00:01.000");
+        let answer = &doc.turns[1].user;
+        assert_eq!(answer.username, Some("Dana".to_string()));
+        assert_eq!(answer.content, "We use make and a script. It works.");
+        assert_eq!(answer.meta.get("start").unwrap(), "00:00:05.000");
+        assert_eq!(answer.meta.get("end").unwrap(), "00:00:09.000");
+    }
+
+    #[test]
+    fn test_srt_diarization_prefixes() {
+        let input = "1\r\n00:00:01,000 --> 00:00:02,000\r\nSPEAKER_00: Hello there.\r\n\r\n\
+                     2\r\n00:00:02,500 --> 00:00:03,000\r\nStill me.\r\n\r\n\
+                     3\r\n00:00:03,000 --> 00:00:05,000\r\n[SPEAKER_01] Hi!\r\n";
+
+        let doc = parse_srt(input, None).unwrap();
+        assert_eq!(doc.turns.len(), 2);
+        assert_eq!(doc.turns[0].user.username, Some("SPEAKER_00".to_string()));
+        assert_eq!(doc.turns[0].user.content, "Hello there. Still me.");
+        assert_eq!(doc.turns[1].user.username, Some("SPEAKER_01".to_string()));
+        assert_eq!(Document::parse(&doc.to_cmf()), doc);
+    }
+
+    #[test]
+    fn test_bracketed_captions_keep_their_text() {
+        let input = "1\n00:00:01,000 --> 00:00:02,000\n[Speaker 1]: Welcome back.\n\n\
+                     2\n00:00:02,000 --> 00:00:04,000\n[Music]\n\n\
+                     3\n00:00:04,000 --> 00:00:05,000\n[laughs] yes\n";
+        let cues = parse_srt_cues(input).unwrap();
+        assert_eq!(cues.len(), 3);
+        assert_eq!(cues[0].speaker, Some("Speaker 1".to_string()));
+        assert_eq!(cues[1].speaker, None);
+        assert_eq!(cues[1].text, "[Music]");
+        assert_eq!(cues[2].speaker, None);
+        assert_eq!(cues[2].text, "[laughs] yes");
+    }
+
+    #[test]
+    fn test_caption_text_is_not_a_speaker() {
+        let input = "1\n00:00:01,000 --> 00:00:02,000\nSPEAKER_00: Read this.\n\n\
+                     2\n00:00:02,000 --> 00:00:04,000\nWARNING: the floor is wet.\n";
+        let cues = parse_srt_cues(input).unwrap();
+        assert_eq!(cues[1].speaker, None);
+        assert_eq!(cues[1].text, "WARNING: the floor is wet.");
+    }
+
+    #[test]
+    fn test_vtt_requires_header() {
+        assert!(parse_vtt("00:00:01.000 --> 00:00:02.000\nHi", None).is_err());
+    }
+
+    #[test]
+    fn test_no_cues() {
+        assert!(parse_vtt("WEBVTT\n\nNOTE nothing said\n", None).is_err());
+        assert!(parse_srt("", None).is_err());
+    }
+}
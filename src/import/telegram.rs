@@ -0,0 +1,201 @@
+//! Telegram Desktop `result.json` chat export importer
+//!
+//! Formatted text (bold, code, links, ...) is converted back to markdown and
+//! photos and files become markdown links to the exported media.
+
+use serde::Deserialize;
+
+use super::{build_document, media_link, ImportError, Message};
+use crate::{Document, Meta};
+
+#[derive(Deserialize)]
+struct Export {
+    messages: Option<Vec<RawMessage>>,
+}
+
+#[derive(Deserialize)]
+struct RawMessage {
+    #[serde(rename = "type")]
+    kind: String,
+    date: Option<String>,
+    from: Option<String>,
+    #[serde(default)]
+    text: Text,
+    photo: Option<String>,
+    file: Option<String>,
+    file_name: Option<String>,
+    media_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Text {
+    Plain(String),
+    Parts(Vec<TextPart>),
+}
+
+impl Default for Text {
+    fn default() -> Self {
+        Text::Plain(String::new())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextPart {
+    Plain(String),
+    Entity {
+        #[serde(rename = "type")]
+        kind: String,
+        text: String,
+        href: Option<String>,
+        language: Option<String>,
+    },
+}
+
+/// Parse a Telegram export; messages from `assistant` become assistant replies
+pub fn parse(input: &str, assistant: Option<&str>) -> Result<Document, ImportError> {
+    Ok(build_document(parse_messages(input)?, assistant))
+}
+
+/// Parse a single-chat Telegram export into messages
+pub fn parse_messages(input: &str) -> Result<Vec<Message>, ImportError> {
+    let export: Export = serde_json::from_str(input)?;
+    let raw = export.messages.ok_or_else(|| {
+        ImportError::Format(
+            "no `messages` array; export a single chat rather than all data".to_string(),
+        )
+    })?;
+
+    Ok(raw
+        .into_iter()
+        // Service messages record joins, pins, calls and so on
+        .filter(|message| message.kind == "message")
+        .map(|message| {
+            let mut parts = Vec::new();
+            if let Some(media) = media_reference(&message) {
+                parts.push(media);
+            }
+            let text = render_text(&message.text);
+            if !text.trim().is_empty() {
+                parts.push(text.trim().to_string());
+            }
+
+            let mut meta = Meta::new();
+            if let Some(date) = message.date {
+                meta.insert("time".to_string(), date);
+            }
+
+            Message {
+                sender: message
+                    .from
+                    .unwrap_or_else(|| "Deleted Account".to_string()),
+                text: parts.join("\n\n"),
+                meta,
+            }
+        })
+        .collect())
+}
+
+fn media_reference(message: &RawMessage) -> Option<String> {
+    if let Some(ref photo) = message.photo {
+        return Some(media_reference_link("photo", photo));
+    }
+    let file = message.file.as_ref()?;
+    let label = message
+        .file_name
+        .as_deref()
+        .or(message.media_type.as_deref())
+        .unwrap_or("file");
+    Some(media_reference_link(label, file))
+}
+
+fn media_reference_link(label: &str, path: &str) -> String {
+    // Media left out of the export is replaced by a "(File not included...)"
+    // notice, which leaves nothing to link to
+    if path.starts_with('(') {
+        format!("*({} not included)*", label)
+    } else {
+        media_link(label, path)
+    }
+}
+
+fn render_text(text: &Text) -> String {
+    match text {
+        Text::Plain(s) => s.clone(),
+        Text::Parts(parts) => parts.iter().map(render_part).collect(),
+    }
+}
+
+fn render_part(part: &TextPart) -> String {
+    let (kind, text, href, language) = match part {
+        TextPart::Plain(s) => return s.clone(),
+        TextPart::Entity {
+            kind,
+            text,
+            href,
+            language,
+        } => (kind.as_str(), text, href, language),
+    };
+
+    match kind {
+        "bold" => format!("**{}**", text),
+        "italic" => format!("*{}*", text),
+        "strikethrough" => format!("~~{}~~", text),
+        "code" => format!("`{}`", text),
+        "pre" => format!(
+            "\n```{}\n{}\n```\n",
+            language.as_deref().unwrap_or(""),
+            text
+        ),
+        "text_link" => match href {
+            Some(href) => format!("[{}]({})", text, href),
+            None => text.clone(),
+        },
+        _ => text.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_export() {
+        let input = r#"{
+            "name": "Support",
+            "type": "personal_chat",
+            "messages": [
+                {"id": 1, "type": "service", "date": "2023-03-12T14:00:00", "action": "phone_call"},
+                {"id": 2, "type": "message", "date": "2023-03-12T14:05:23", "from": "Alice", "text": "Hello"},
+                {"id": 3, "type": "message", "date": "2023-03-12T14:06:00", "from": "Bob",
+                 "text": ["Run ", {"type": "code", "text": "make"}, " and see ", {"type": "text_link", "text": "docs", "href": "https://example.com"}]},
+                {"id": 4, "type": "message", "date": "2023-03-12T14:07:00", "from": "Alice",
+                 "photo": "photos/photo_1@12-03-2023_14-07-00.jpg", "text": "Screenshot"},
+                {"id": 5, "type": "message", "date": "2023-03-12T14:08:00", "from": "Bob",
+                 "photo": "(File not included. Change data exporting settings to download.)", "text": ""}
+            ]
+        }"#;
+
+        let messages = parse_messages(input).unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0].meta.get("time").unwrap(), "2023-03-12T14:05:23");
+        assert_eq!(
+            messages[1].text,
+            "Run `make` and see [docs](https://example.com)"
+        );
+        assert_eq!(
+            messages[2].text,
+            "[photo](photos/photo_1@12-03-2023_14-07-00.jpg)\n\nScreenshot"
+        );
+        assert_eq!(messages[3].text, "*(photo not included)*");
+    }
+
+    #[test]
+    fn test_full_export_rejected() {
+        assert!(matches!(
+            parse(r#"{"chats": {"list": []}}"#, None),
+            Err(ImportError::Format(_))
+        ));
+    }
+}
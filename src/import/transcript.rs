@@ -0,0 +1,353 @@
+//! Heuristic importer for plain-text and markdown transcripts
+//!
+//! Recognises three layouts:
+//! - role headings (`## User` / `## Assistant`, as in Open WebUI exports)
+//! - role prefixes (`User: ...` / `Assistant: ...`, also `**User:**`)
+//! - Aider's `.aider.chat.history.md` (`#### ` user lines)
+//!
+//! Every layout is scored and the best one wins; the score is reported as a
+//! confidence between 0 and 1 so callers can reject weak guesses.
+
+use std::str::FromStr;
+
+use super::ImportError;
+use crate::{Document, Turn, UserMessage};
+
+const USER_LABELS: &[&str] = &["user", "human", "you", "me", "prompt", "question", "q"];
+const ASSISTANT_LABELS: &[&str] = &[
+    "assistant",
+    "ai",
+    "bot",
+    "model",
+    "chatgpt",
+    "gpt",
+    "claude",
+    "gemini",
+    "copilot",
+    "response",
+    "answer",
+    "a",
+];
+
+/// The transcript layout that was detected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    Headings,
+    Prefixes,
+    Aider,
+}
+
+impl std::fmt::Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Layout::Headings => write!(f, "headings"),
+            Layout::Prefixes => write!(f, "prefixes"),
+            Layout::Aider => write!(f, "aider"),
+        }
+    }
+}
+
+/// Custom role labels, written `USER/ASSISTANT` with `|` between alternatives
+///
+/// For example `Customer/Agent` or `Q|Question/A|Answer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub user: Vec<String>,
+    pub assistant: Vec<String>,
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Self {
+            user: USER_LABELS.iter().map(|s| s.to_string()).collect(),
+            assistant: ASSISTANT_LABELS.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, assistant) = s
+            .split_once('/')
+            .ok_or_else(|| "expected USER/ASSISTANT labels".to_string())?;
+        let labels = |side: &str| -> Vec<String> {
+            side.split('|')
+                .map(|label| label.trim().to_lowercase())
+                .filter(|label| !label.is_empty())
+                .collect()
+        };
+        let pattern = Pattern {
+            user: labels(user),
+            assistant: labels(assistant),
+        };
+        if pattern.user.is_empty() || pattern.assistant.is_empty() {
+            return Err("both USER and ASSISTANT labels are required".to_string());
+        }
+        Ok(pattern)
+    }
+}
+
+impl Pattern {
+    fn role_of(&self, label: &str) -> Option<Role> {
+        let label = label.trim().to_lowercase();
+        if self.user.contains(&label) {
+            Some(Role::User)
+        } else if self.assistant.contains(&label) {
+            Some(Role::Assistant)
+        } else {
+            None
+        }
+    }
+}
+
+/// An imported transcript along with how it was detected
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transcript {
+    pub document: Document,
+    pub layout: Layout,
+    pub confidence: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Role {
+    User,
+    Assistant,
+}
+
+/// A run of text attributed to one role
+type Segment = (Role, String);
+
+/// Detect the transcript layout and convert it to a document
+pub fn parse(input: &str, pattern: Option<&Pattern>) -> Result<Transcript, ImportError> {
+    let default_pattern = Pattern::default();
+    let pattern = pattern.unwrap_or(&default_pattern);
+
+    let candidates = [
+        (Layout::Aider, split_aider(input)),
+        (Layout::Headings, split_headings(input, pattern)),
+        (Layout::Prefixes, split_prefixes(input, pattern)),
+    ];
+
+    // Earlier candidates win ties
+    let mut best: Option<(Layout, Vec<Segment>, f64)> = None;
+    for (layout, segments) in candidates {
+        let confidence = score(layout, input, &segments);
+        if best.as_ref().is_none_or(|b| confidence > b.2) {
+            best = Some((layout, segments, confidence));
+        }
+    }
+    let (layout, segments, confidence) = best.unwrap();
+
+    if confidence == 0.0 {
+        return Err(ImportError::Format(
+            "no role headings or prefixes found; try --pattern".to_string(),
+        ));
+    }
+
+    Ok(Transcript {
+        document: build(segments),
+        layout,
+        confidence,
+    })
+}
+
+/// Split on `## Role` heading lines
+fn split_headings(input: &str, pattern: &Pattern) -> Vec<Segment> {
+    split_on_markers(input, |line| {
+        let text = line.trim_start_matches('#');
+        if text.len() == line.len() || line.len() - text.len() > 6 || !text.starts_with(' ') {
+            return None;
+        }
+        let label = text.trim().trim_end_matches(':').trim_matches('*');
+        pattern.role_of(label).map(|role| (role, String::new()))
+    })
+}
+
+/// Split on `Role: text` prefixes, optionally in bold
+fn split_prefixes(input: &str, pattern: &Pattern) -> Vec<Segment> {
+    split_on_markers(input, |line| {
+        let (label, rest) = line.split_once(':')?;
+        let label = label.trim_start_matches("**").trim_end_matches("**");
+        let rest = rest.strip_prefix("**").unwrap_or(rest);
+        pattern
+            .role_of(label)
+            .map(|role| (role, rest.trim_start().to_string()))
+    })
+}
+
+fn split_on_markers(input: &str, marker: impl Fn(&str) -> Option<(Role, String)>) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for line in input.lines() {
+        if let Some((role, first)) = marker(line) {
+            segments.push((role, first));
+        } else if let Some((_, text)) = segments.last_mut() {
+            text.push('\n');
+            text.push_str(line);
+        }
+        // Anything before the first marker is a preamble and is dropped
+    }
+    segments
+}
+
+/// Aider logs: `#### ` lines are the user, `# aider chat started at` starts a session
+fn split_aider(input: &str) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for line in input.lines() {
+        if line.starts_with("# aider chat started at") {
+            continue;
+        }
+        if let Some(text) = line.strip_prefix("####") {
+            let text = text.trim();
+            match segments.last_mut() {
+                Some((Role::User, content)) => {
+                    content.push('\n');
+                    content.push_str(text);
+                }
+                _ => segments.push((Role::User, text.to_string())),
+            }
+        } else {
+            match segments.last_mut() {
+                Some((Role::Assistant, content)) => {
+                    content.push('\n');
+                    content.push_str(line);
+                }
+                Some(_) => segments.push((Role::Assistant, line.to_string())),
+                None => {}
+            }
+        }
+    }
+    segments
+}
+
+/// Rate how well a layout explains the input, from 0 to 1
+fn score(layout: Layout, input: &str, segments: &[Segment]) -> f64 {
+    let markers: Vec<Role> = segments
+        .iter()
+        .filter(|(_, text)| !text.trim().is_empty())
+        .map(|(role, _)| *role)
+        .collect();
+    if markers.is_empty() {
+        return 0.0;
+    }
+
+    if layout == Layout::Aider {
+        if input.contains("# aider chat started at") {
+            return 0.95;
+        }
+        // `####` on its own is an ordinary heading; only trust it a little
+        return 0.2;
+    }
+
+    let has_user = markers.contains(&Role::User);
+    let has_assistant = markers.contains(&Role::Assistant);
+    let mut confidence = if has_user && has_assistant { 0.5 } else { 0.2 };
+
+    let transitions = markers.len().saturating_sub(1);
+    if transitions > 0 {
+        let alternating = markers.windows(2).filter(|w| w[0] != w[1]).count();
+        confidence += 0.3 * alternating as f64 / transitions as f64;
+    }
+    if markers[0] == Role::User {
+        confidence += 0.1;
+    }
+    confidence += 0.1 * markers.len().min(6) as f64 / 6.0;
+
+    confidence.min(1.0)
+}
+
+fn build(segments: Vec<Segment>) -> Document {
+    let mut turns: Vec<Turn> = Vec::new();
+    for (role, text) in segments {
+        let text = text.trim().to_string();
+        match role {
+            Role::User => turns.push(Turn {
+                user: UserMessage {
+                    content: text,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            Role::Assistant => {
+                if turns.is_empty() {
+                    turns.push(Turn::default());
+                }
+                let turn = turns.last_mut().unwrap();
+                if !turn.assistant.is_empty() {
+                    turn.assistant.push_str("\n\n");
+                }
+                turn.assistant.push_str(&text);
+            }
+        }
+    }
+    Document {
+        turns,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heading_layout() {
+        let input = "# Chat export\n\n### USER\nWhat is Rust?\n\n### ASSISTANT\nA systems language.\n\n## Details\n\nIt is fast.\n\n### USER\nThanks\n";
+        let transcript = parse(input, None).unwrap();
+        assert_eq!(transcript.layout, Layout::Headings);
+        assert!(transcript.confidence > 0.8);
+        let doc = transcript.document;
+        assert_eq!(doc.turns.len(), 2);
+        assert_eq!(doc.turns[0].user.content, "What is Rust?");
+        assert_eq!(
+            doc.turns[0].assistant,
+            "A systems language.\n\n## Details\n\nIt is fast."
+        );
+        assert_eq!(doc.turns[1].user.content, "Thanks");
+    }
+
+    #[test]
+    fn test_prefix_layout() {
+        let input = "User: Hi there\nhow are you?\nAssistant: Fine, thanks.\n**User:** Great.\n";
+        let transcript = parse(input, None).unwrap();
+        assert_eq!(transcript.layout, Layout::Prefixes);
+        let doc = transcript.document;
+        assert_eq!(doc.turns.len(), 2);
+        assert_eq!(doc.turns[0].user.content, "Hi there\nhow are you?");
+        assert_eq!(doc.turns[0].assistant, "Fine, thanks.");
+        assert_eq!(doc.turns[1].user.content, "Great.");
+    }
+
+    #[test]
+    fn test_aider_layout() {
+        let input = "\n# aider chat started at 2024-05-01 10:00:00\n\n> Aider v0.30.1\n\n#### add a hello function  \n#### in main.rs  \n\nHere is the change.\n\n> Applied edit to main.rs\n";
+        let transcript = parse(input, None).unwrap();
+        assert_eq!(transcript.layout, Layout::Aider);
+        let doc = transcript.document;
+        assert_eq!(doc.turns.len(), 1);
+        assert_eq!(
+            doc.turns[0].user.content,
+            "add a hello function\nin main.rs"
+        );
+        assert_eq!(
+            doc.turns[0].assistant,
+            "Here is the change.\n\n> Applied edit to main.rs"
+        );
+        assert_eq!(Document::parse(&doc.to_cmf()).turns.len(), 1);
+    }
+
+    #[test]
+    fn test_custom_pattern() {
+        let pattern: Pattern = "Customer/Agent|Support".parse().unwrap();
+        let input = "Customer: My order is late\nSupport: Sorry about that!\n";
+        let doc = parse(input, Some(&pattern)).unwrap().document;
+        assert_eq!(doc.turns[0].user.content, "My order is late");
+        assert_eq!(doc.turns[0].assistant, "Sorry about that!");
+    }
+
+    #[test]
+    fn test_undetectable() {
+        assert!(parse("Just some notes.\nNothing else.", None).is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! WhatsApp `.txt` chat export importer
+//!
+//! Handles both the iOS layout (`[12/03/2023, 14:05:23] Alice: Hi`) and the
+//! Android layout (`12/03/2023, 14:05 - Alice: Hi`), with day-first,
+//! month-first and ISO dates, 12- and 24-hour clocks, and `/`, `.` or `-`
+//! date separators.
+
+use regex::Regex;
+
+use super::{build_document, media_link, ImportError, Message};
+use crate::{Document, Meta};
+
+/// Invisible direction marks WhatsApp scatters through exports
+const DIRECTION_MARKS: &[char] = &['\u{200e}', '\u{200f}'];
+
+/// Parse a WhatsApp export; messages from `assistant` become assistant replies
+pub fn parse(input: &str, assistant: Option<&str>) -> Result<Document, ImportError> {
+    Ok(build_document(parse_messages(input)?, assistant))
+}
+
+/// A message header before its date has been normalised
+struct RawMessage {
+    date: [u32; 3],
+    time: String,
+    sender: String,
+    lines: Vec<String>,
+}
+
+/// Parse a WhatsApp export into messages, merging continuation lines
+pub fn parse_messages(input: &str) -> Result<Vec<Message>, ImportError> {
+    let header = Regex::new(
+        r"^\[?(\d{1,4})[./-](\d{1,2})[./-](\d{1,4}),? (\d{1,2}[:.]\d{2}(?:[:.]\d{2})?(?:[\s\u{202f}]?[AaPp]\.?\s?[Mm]\.?)?)\]?(?: - | )(.*)$",
+    )
+    .unwrap();
+
+    let mut raw: Vec<RawMessage> = Vec::new();
+    // Whether the last header was a message (continuations of system notices are dropped)
+    let mut in_message = false;
+
+    for line in input.lines() {
+        let line = line
+            .trim_start_matches(DIRECTION_MARKS)
+            .trim_end_matches('\r');
+        if let Some(caps) = header.captures(line) {
+            let rest = &caps[5];
+            in_message = false;
+            // System notices ("Messages and calls are end-to-end encrypted") have no sender
+            if let Some((sender, text)) = rest.split_once(": ") {
+                let date = [&caps[1], &caps[2], &caps[3]].map(|part| part.parse().unwrap_or(0));
+                raw.push(RawMessage {
+                    date,
+                    time: caps[4].to_string(),
+                    sender: sender.trim_matches(DIRECTION_MARKS).to_string(),
+                    lines: vec![text.to_string()],
+                });
+                in_message = true;
+            }
+        } else if in_message {
+            if let Some(message) = raw.last_mut() {
+                message.lines.push(line.to_string());
+            }
+        }
+    }
+
+    if raw.is_empty() {
+        return Err(ImportError::Format(
+            "no WhatsApp messages found".to_string(),
+        ));
+    }
+
+    let order = detect_date_order(&raw);
+    Ok(raw
+        .into_iter()
+        .map(|message| {
+            let mut meta = Meta::new();
+            if let Some(time) = normalize_timestamp(message.date, &message.time, order) {
+                meta.insert("time".to_string(), time);
+            }
+            let text = message
+                .lines
+                .iter()
+                .map(|line| convert_media(line))
+                .collect::<Vec<_>>()
+                .join("\n");
+            Message {
+                sender: message.sender,
+                text: text.trim().to_string(),
+                meta,
+            }
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DateOrder {
+    DayMonthYear,
+    MonthDayYear,
+    YearMonthDay,
+}
+
+/// Work out the date layout from the whole export, since single dates are ambiguous
+fn detect_date_order(messages: &[RawMessage]) -> DateOrder {
+    if messages.iter().any(|m| m.date[0] > 31) {
+        return DateOrder::YearMonthDay;
+    }
+    if messages.iter().any(|m| m.date[0] > 12) {
+        return DateOrder::DayMonthYear;
+    }
+    if messages.iter().any(|m| m.date[1] > 12) {
+        return DateOrder::MonthDayYear;
+    }
+    // Still ambiguous: 12-hour clocks are mostly paired with US-style dates
+    if messages.iter().any(|m| clock_suffix(&m.time).is_some()) {
+        DateOrder::MonthDayYear
+    } else {
+        DateOrder::DayMonthYear
+    }
+}
+
+/// Returns `Some(true)` for PM and `Some(false)` for AM
+fn clock_suffix(time: &str) -> Option<bool> {
+    let letters: String = time
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    match letters.as_str() {
+        "am" => Some(false),
+        "pm" => Some(true),
+        _ => None,
+    }
+}
+
+/// Build an ISO 8601 timestamp (`2023-03-12T14:05:00`)
+fn normalize_timestamp(date: [u32; 3], time: &str, order: DateOrder) -> Option<String> {
+    let (year, month, day) = match order {
+        DateOrder::DayMonthYear => (date[2], date[1], date[0]),
+        DateOrder::MonthDayYear => (date[2], date[0], date[1]),
+        DateOrder::YearMonthDay => (date[0], date[1], date[2]),
+    };
+    let year = if year < 100 { year + 2000 } else { year };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let digits: Vec<u32> = time
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .filter_map(|part| part.parse().ok())
+        .collect();
+    let (mut hour, minute) = (*digits.first()?, *digits.get(1)?);
+    let second = digits.get(2).copied().unwrap_or(0);
+    match clock_suffix(time) {
+        Some(true) if hour < 12 => hour += 12,
+        Some(false) if hour == 12 => hour = 0,
+        _ => {}
+    }
+
+    Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year, month, day, hour, minute, second
+    ))
+}
+
+/// Turn media placeholders into markdown links, or a note where the
+/// export left the media out
+fn convert_media(line: &str) -> String {
+    let line: String = line
+        .chars()
+        .filter(|c| !DIRECTION_MARKS.contains(c))
+        .collect();
+
+    // iOS export with media: `<attached: 00000012-PHOTO-2023-03-12-14-05-23.jpg>`
+    if let (Some(start), Some(end)) = (line.find("<attached: "), line.rfind('>')) {
+        if start < end {
+            let path = line[start + "<attached: ".len()..end].trim();
+            return format!(
+                "{}{}{}",
+                &line[..start],
+                media_link(path, path),
+                &line[end + 1..]
+            );
+        }
+    }
+
+    // Android export with media: `IMG-20230312-WA0001.jpg (file attached)`
+    if let Some(path) = line.strip_suffix(" (file attached)") {
+        return media_link(path.trim(), path.trim());
+    }
+
+    // Export without media: `<Media omitted>` or `image omitted`, which
+    // leave nothing to link to
+    let trimmed = line.trim();
+    if trimmed == "<Media omitted>" {
+        return "*(media omitted)*".to_string();
+    }
+    if trimmed.ends_with(" omitted") && trimmed.split_whitespace().count() <= 3 {
+        return format!("*({})*", trimmed);
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ios_export() {
+        let input =
+            "\u{200e}[12/03/2023, 14:05:23] Alice: Messages and calls are end-to-end encrypted.\n\
+                     [12/03/2023, 14:05:30] Alice: Hi there\n\
+                     this continues the message\n\
+                     [13/03/2023, 09:00:00] Bob: \u{200e}image omitted\n";
+        let messages = parse_messages(input).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1].text, "Hi there\nthis continues the message");
+        assert_eq!(messages[1].meta.get("time").unwrap(), "2023-03-12T14:05:30");
+        assert_eq!(messages[2].sender, "Bob");
+        assert_eq!(messages[2].text, "*(image omitted)*");
+    }
+
+    #[test]
+    fn test_android_us_export() {
+        let input = "3/12/23, 2:05 PM - Messages and calls are end-to-end encrypted.\n\
+                     3/12/23, 2:05 PM - Alice: Hello\n\
+                     3/12/23, 12:10 AM - Bob: IMG-20230312-WA0001.jpg (file attached)\n\
+                     3/12/23, 12:11 AM - Bob: <Media omitted>\n";
+        let messages = parse_messages(input).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].meta.get("time").unwrap(), "2023-03-12T14:05:00");
+        assert_eq!(messages[1].meta.get("time").unwrap(), "2023-03-12T00:10:00");
+        assert_eq!(
+            messages[1].text,
+            "[IMG-20230312-WA0001.jpg](IMG-20230312-WA0001.jpg)"
+        );
+        assert_eq!(messages[2].text, "*(media omitted)*");
+    }
+
+    #[test]
+    fn test_dotted_dates_and_attachments() {
+        let input = "12.03.23, 14:05 - Alice: <attached: 00000012-PHOTO.jpg>\n";
+        let messages = parse_messages(input).unwrap();
+        assert_eq!(messages[0].meta.get("time").unwrap(), "2023-03-12T14:05:00");
+        assert_eq!(messages[0].text, "[00000012-PHOTO.jpg](00000012-PHOTO.jpg)");
+    }
+
+    #[test]
+    fn test_not_whatsapp() {
+        assert!(parse("just some text", None).is_err());
+    }
+}
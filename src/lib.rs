@@ -3,30 +3,177 @@
 //! A markdown-based interchange format for LLM conversations.
 //! User messages are blockquotes (`>`), assistant messages are plain markdown.
 
+pub mod annotation;
+pub mod backend;
+pub mod branch;
+pub mod chat;
+pub mod collection;
+pub mod compact;
+pub mod diff;
+pub mod dpo;
+pub mod edit;
+pub mod file;
+pub mod grep;
+pub mod html;
+pub mod import;
+pub mod merge;
+pub mod meta;
+pub mod response;
+pub mod select;
+pub mod site;
+pub mod split;
+pub mod stats;
 pub mod terminal_renderer;
+pub mod tokenizer;
+pub mod trim;
+
+pub use edit::EditError;
+pub use meta::Meta;
 
 use serde::Serialize;
 
+/// Metadata key of the comment line that starts an alternate reply
+const ALTERNATE_KEY: &str = "alternate";
+
 /// A parsed user message with optional attribution
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
 pub struct UserMessage {
     /// Optional username (from `@username:` prefix)
     pub username: Option<String>,
     /// The message content (without the `>` prefix)
     pub content: String,
+    /// Metadata from a `> <!-- cmf: ... -->` line inside the user block
+    #[serde(skip_serializing_if = "Meta::is_empty")]
+    pub meta: Meta,
 }
 
 /// A single turn in a conversation (user + assistant)
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
 pub struct Turn {
     pub user: UserMessage,
     pub assistant: String,
+    /// Metadata from `<!-- cmf: ... -->` lines leading or following the
+    /// assistant reply, such as review annotations
+    #[serde(skip_serializing_if = "Meta::is_empty")]
+    pub meta: Meta,
+    /// Other candidate replies, each after a `<!-- cmf: alternate=N -->` line
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alternates: Vec<Alternate>,
+    /// Source text of the turn, written back verbatim while it still matches
+    #[serde(skip)]
+    pub span: Span,
+}
+
+/// A candidate assistant reply kept alongside the one the conversation follows
+///
+/// Ratings and labels are ordinary metadata (`rating=4 label=verbose`); a
+/// `preferred` key marks the candidate to prefer over the main reply.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Alternate {
+    pub content: String,
+    #[serde(skip_serializing_if = "Meta::is_empty")]
+    pub meta: Meta,
 }
 
 /// A parsed CMF document
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
 pub struct Document {
+    /// Metadata from the frontmatter block (`title`, `date`, `tags`, ...)
+    #[serde(skip_serializing_if = "Meta::is_empty")]
+    pub meta: Meta,
     pub turns: Vec<Turn>,
+    /// Source text before the first user line (frontmatter and any preamble)
+    #[serde(skip)]
+    pub preamble: Span,
+}
+
+/// The source text something was parsed from
+///
+/// `to_cmf` writes a span back verbatim as long as it still parses to the
+/// same content, so unedited turns keep their original formatting. Spans
+/// never affect equality.
+#[derive(Debug, Clone, Default)]
+pub struct Span(Option<String>);
+
+impl Span {
+    pub fn new(text: impl Into<String>) -> Self {
+        Span(Some(text.into()))
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+
+    /// Forget the source text so the value is always re-serialized
+    pub fn clear(&mut self) {
+        self.0 = None;
+    }
+}
+
+impl PartialEq for Span {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Turn {
+    /// Serialize the turn in canonical form, ignoring its source span
+    pub fn to_cmf(&self) -> String {
+        let mut lines = Vec::new();
+
+        // Format user message with > prefix
+        let user_content = if let Some(ref username) = self.user.username {
+            format!("@{}: {}", username, self.user.content)
+        } else {
+            self.user.content.clone()
+        };
+
+        // Handle multiline user messages; an empty message still needs a `>`
+        if user_content.is_empty() {
+            lines.push(">".to_string());
+        }
+        for line in user_content.lines() {
+            lines.push(format!("> {}", line));
+        }
+        if !self.user.meta.is_empty() {
+            lines.push(format!("> {}", meta::format_comment(&self.user.meta)));
+        }
+
+        if !self.meta.is_empty() {
+            lines.push(meta::format_comment(&self.meta));
+        }
+
+        // Add assistant response (if any)
+        if !self.assistant.is_empty() {
+            lines.push(escape_assistant(&self.assistant));
+        }
+
+        for (i, alternate) in self.alternates.iter().enumerate() {
+            let mut meta = alternate.meta.clone();
+            meta.insert(ALTERNATE_KEY.to_string(), (i + 1).to_string());
+            lines.push(String::new());
+            lines.push(meta::format_comment(&meta));
+            if !alternate.content.is_empty() {
+                lines.push(escape_assistant(&alternate.content));
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    /// The main reply followed by the alternates, with their metadata
+    pub fn candidates(&self) -> impl Iterator<Item = (&str, &Meta)> {
+        std::iter::once((self.assistant.as_str(), &self.meta)).chain(
+            self.alternates
+                .iter()
+                .map(|alternate| (alternate.content.as_str(), &alternate.meta)),
+        )
+    }
+
+    fn matches_source(&self, text: &str) -> bool {
+        let parsed = Document::parse(text);
+        parsed.turns.len() == 1 && parsed.turns[0] == *self
+    }
 }
 
 impl std::fmt::Display for Document {
@@ -37,33 +184,35 @@ impl std::fmt::Display for Document {
 
 impl Document {
     /// Serialize the document back to CMF markdown format
+    ///
+    /// Parts whose source span still matches their content are written back
+    /// verbatim; everything else is written in canonical form.
     pub fn to_cmf(&self) -> String {
         let mut output = String::new();
 
+        let (verbatim, notes) = match self.preamble.text() {
+            Some(text) => self.split_preamble(text),
+            None => (false, ""),
+        };
+        let preamble = match self.preamble.text() {
+            Some(text) if verbatim => text.to_string(),
+            _ if self.meta.is_empty() => notes.to_string(),
+            _ if notes.is_empty() => meta::format_frontmatter(&self.meta),
+            _ => format!("{}\n\n{}", meta::format_frontmatter(&self.meta), notes),
+        };
+        if !preamble.is_empty() {
+            output.push_str(&preamble);
+            output.push_str("\n\n");
+        }
+
         for (i, turn) in self.turns.iter().enumerate() {
             // Add blank line between turns (but not before first)
             if i > 0 {
                 output.push_str("\n\n");
             }
-
-            // Format user message with > prefix
-            let user_content = if let Some(ref username) = turn.user.username {
-                format!("@{}: {}", username, turn.user.content)
-            } else {
-                turn.user.content.clone()
-            };
-
-            // Handle multiline user messages
-            for line in user_content.lines() {
-                output.push_str("> ");
-                output.push_str(line);
-                output.push('\n');
-            }
-
-            // Add assistant response (if any)
-            if !turn.assistant.is_empty() {
-                output.push_str(&turn.assistant);
-                output.push('\n');
+            match turn.span.text() {
+                Some(text) if turn.matches_source(text) => output.push_str(text),
+                _ => output.push_str(&turn.to_cmf()),
             }
         }
 
@@ -71,9 +220,24 @@ impl Document {
         output.trim_end().to_string()
     }
 
+    /// Whether the source preamble can be written back as-is, and the text
+    /// in it after the frontmatter, which is kept when only the metadata
+    /// changed
+    fn split_preamble<'a>(&self, text: &'a str) -> (bool, &'a str) {
+        if text.lines().any(|line| line.starts_with('>')) {
+            return (false, "");
+        }
+        let (meta, lines) = meta::parse_frontmatter(text).unwrap_or_default();
+        let start: usize = text.split_inclusive('\n').take(lines).map(str::len).sum();
+        let notes = text[start..].trim_matches('\n');
+        (meta == self.meta, notes)
+    }
+
     /// Parse a CMF document from markdown text
     pub fn parse(input: &str) -> Self {
         let mut turns = Vec::new();
+        let mut preamble_lines: Vec<&str> = Vec::new();
+        let mut span_lines: Vec<&str> = Vec::new();
         let mut current_user_lines: Vec<String> = Vec::new();
         let mut current_assistant_lines: Vec<String> = Vec::new();
         let mut in_user_block = false;
@@ -85,15 +249,19 @@ impl Document {
             if is_user_line {
                 // If we were collecting assistant content, finalize the previous turn
                 if seen_first_user && !in_user_block && !current_user_lines.is_empty() {
-                    let user = parse_user_block(&current_user_lines);
-                    let assistant = trim_assistant_block(&current_assistant_lines);
-                    turns.push(Turn { user, assistant });
+                    turns.push(finish_turn(
+                        &current_user_lines,
+                        &current_assistant_lines,
+                        &span_lines,
+                    ));
                     current_user_lines.clear();
                     current_assistant_lines.clear();
+                    span_lines.clear();
                 }
 
                 in_user_block = true;
                 seen_first_user = true;
+                span_lines.push(line);
                 // Strip the leading `>` and optional single space
                 let content = line.strip_prefix('>').unwrap_or(line);
                 let content = content.strip_prefix(' ').unwrap_or(content);
@@ -104,20 +272,57 @@ impl Document {
                     in_user_block = false;
                 }
                 if seen_first_user {
+                    span_lines.push(line);
                     current_assistant_lines.push(line.to_string());
+                } else {
+                    // Lines before the first user block are the preamble/frontmatter
+                    preamble_lines.push(line);
                 }
-                // Lines before the first user block are ignored (preamble/frontmatter)
             }
         }
 
         // Finalize the last turn if we have user content
         if !current_user_lines.is_empty() {
-            let user = parse_user_block(&current_user_lines);
-            let assistant = trim_assistant_block(&current_assistant_lines);
-            turns.push(Turn { user, assistant });
+            turns.push(finish_turn(
+                &current_user_lines,
+                &current_assistant_lines,
+                &span_lines,
+            ));
+        }
+
+        let preamble = preamble_lines.join("\n");
+        let doc_meta = meta::parse_frontmatter(&preamble)
+            .map(|(meta, _)| meta)
+            .unwrap_or_default();
+
+        Document {
+            meta: doc_meta,
+            turns,
+            preamble: Span::new(preamble.trim_end()),
         }
+    }
+
+    /// The `title` from the frontmatter, if any
+    pub fn title(&self) -> Option<&str> {
+        self.meta.get("title").map(String::as_str)
+    }
 
-        Document { turns }
+    /// The `date` from the frontmatter, if any
+    pub fn date(&self) -> Option<&str> {
+        self.meta.get("date").map(String::as_str)
+    }
+
+    /// The `system` prompt from the frontmatter, if any
+    pub fn system(&self) -> Option<&str> {
+        self.meta.get("system").map(String::as_str)
+    }
+
+    /// The `tags` list from the frontmatter
+    pub fn tags(&self) -> Vec<String> {
+        self.meta
+            .get("tags")
+            .map(|tags| meta::parse_list(tags))
+            .unwrap_or_default()
     }
 
     /// Check if a document appears to be valid CMF
@@ -130,10 +335,9 @@ impl Document {
     pub fn check(input: &str) -> Vec<Issue> {
         let mut issues = Vec::new();
         let mut prev_was_blank_or_start = true;
-        let mut line_num = 0;
 
-        for line in input.lines() {
-            line_num += 1;
+        for (i, line) in input.lines().enumerate() {
+            let line_num = i + 1;
 
             // Check for user lines that don't start after blank/BOF
             if line.starts_with('>') && !prev_was_blank_or_start {
@@ -162,7 +366,37 @@ pub struct Issue {
     pub message: String,
 }
 
+fn finish_turn(user_lines: &[String], assistant_lines: &[String], span_lines: &[&str]) -> Turn {
+    let user = parse_user_block(user_lines);
+    let (assistant, meta, alternates) = parse_assistant_block(assistant_lines);
+    // The span runs up to the next turn, minus the blank lines separating them
+    let end = span_lines
+        .iter()
+        .rposition(|line| !line.trim().is_empty())
+        .map_or(0, |i| i + 1);
+    Turn {
+        user,
+        assistant,
+        meta,
+        alternates,
+        span: Span::new(span_lines[..end].join("\n")),
+    }
+}
+
 fn parse_user_block(lines: &[String]) -> UserMessage {
+    // Pull out metadata comments before looking at the content
+    let mut meta = Meta::new();
+    let lines: Vec<String> = lines
+        .iter()
+        .filter(|line| match meta::parse_comment(line) {
+            Some(parsed) => {
+                meta.extend(parsed);
+                false
+            }
+            None => true,
+        })
+        .cloned()
+        .collect();
     let content = lines.join("\n");
 
     // Check for @username: prefix on first line
@@ -179,6 +413,7 @@ fn parse_user_block(lines: &[String]) -> UserMessage {
                 return UserMessage {
                     username: Some(username),
                     content: format!("{}{}", first_content, rest),
+                    meta,
                 };
             }
         }
@@ -187,9 +422,67 @@ fn parse_user_block(lines: &[String]) -> UserMessage {
     UserMessage {
         username: None,
         content,
+        meta,
     }
 }
 
+fn parse_assistant_block(lines: &[String]) -> (String, Meta, Vec<Alternate>) {
+    // Each alternate marker starts a new candidate reply
+    let mut markers = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(mut meta) = meta::parse_comment(line) {
+            if meta.remove(ALTERNATE_KEY).is_some() {
+                markers.push((i, meta));
+            }
+        }
+    }
+
+    let end = markers.first().map_or(lines.len(), |(i, _)| *i);
+    let (assistant, meta) = parse_reply(&lines[..end]);
+    let alternates = markers
+        .iter()
+        .enumerate()
+        .map(|(n, (start, marker_meta))| {
+            let end = markers.get(n + 1).map_or(lines.len(), |(i, _)| *i);
+            let (content, mut meta) = parse_reply(&lines[start + 1..end]);
+            meta.extend(marker_meta.clone());
+            Alternate { content, meta }
+        })
+        .collect();
+
+    (assistant, meta, alternates)
+}
+
+fn parse_reply(lines: &[String]) -> (String, Meta) {
+    // Metadata comments may lead the reply or follow it, but not interrupt it
+    let mut meta = Meta::new();
+    let mut start = 0;
+    for line in lines {
+        if line.trim().is_empty() {
+            start += 1;
+        } else if let Some(parsed) = meta::parse_comment(line) {
+            meta.extend(parsed);
+            start += 1;
+        } else {
+            break;
+        }
+    }
+    let mut end = lines.len();
+    while end > start {
+        let line = &lines[end - 1];
+        if line.trim().is_empty() {
+            end -= 1;
+        } else if let Some(parsed) = meta::parse_comment(line) {
+            meta.extend(parsed);
+            end -= 1;
+        } else {
+            break;
+        }
+    }
+
+    (trim_assistant_block(&lines[start..end]), meta)
+}
+
 fn trim_assistant_block(lines: &[String]) -> String {
     // Trim leading and trailing blank lines
     let start = lines.iter().position(|l| !l.trim().is_empty()).unwrap_or(0);
@@ -206,6 +499,21 @@ fn trim_assistant_block(lines: &[String]) -> String {
     lines[start..end].join("\n")
 }
 
+/// Indent lines starting with `>` so they are not read back as user lines
+pub(crate) fn escape_assistant(content: &str) -> String {
+    content
+        .split('\n')
+        .map(|line| {
+            if line.starts_with('>') {
+                format!(" {}", line)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 /// OpenAI Chat Completions message format
 #[derive(Debug, Clone, Serialize)]
 pub struct ChatMessage {
@@ -304,7 +612,10 @@ Got it!"#;
 
         let doc = Document::parse(input);
         assert_eq!(doc.turns.len(), 1);
-        assert_eq!(doc.turns[0].user.content, "This is a\nmultiline\nuser message");
+        assert_eq!(
+            doc.turns[0].user.content,
+            "This is a\nmultiline\nuser message"
+        );
     }
 
     #[test]
@@ -347,15 +658,16 @@ Third paragraph."#;
     #[test]
     fn test_to_cmf_simple() {
         let doc = Document {
-            turns: vec![
-                Turn {
-                    user: UserMessage {
-                        username: None,
-                        content: "Hello!".to_string(),
-                    },
-                    assistant: "Hi there!".to_string(),
+            turns: vec![Turn {
+                user: UserMessage {
+                    username: None,
+                    content: "Hello!".to_string(),
+                    ..Default::default()
                 },
-            ],
+                assistant: "Hi there!".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
         };
         assert_eq!(doc.to_cmf(), "> Hello!\nHi there!");
     }
@@ -367,9 +679,12 @@ Third paragraph."#;
                 user: UserMessage {
                     username: None,
                     content: "Line one\nLine two".to_string(),
+                    ..Default::default()
                 },
                 assistant: "Got it!".to_string(),
+                ..Default::default()
             }],
+            ..Default::default()
         };
         assert_eq!(doc.to_cmf(), "> Line one\n> Line two\nGot it!");
     }
@@ -381,9 +696,12 @@ Third paragraph."#;
                 user: UserMessage {
                     username: Some("alice".to_string()),
                     content: "Hello".to_string(),
+                    ..Default::default()
                 },
                 assistant: "Hi Alice!".to_string(),
+                ..Default::default()
             }],
+            ..Default::default()
         };
         assert_eq!(doc.to_cmf(), "> @alice: Hello\nHi Alice!");
     }
@@ -408,6 +726,97 @@ The answer is 4."#;
         }
     }
 
+    #[test]
+    fn test_message_meta_roundtrip() {
+        let input = "> @alice: Hi\n> <!-- cmf: time=2023-03-12T14:05:00 -->\n<!-- cmf: time=2023-03-12T14:06:00 -->\nHello!";
+
+        let doc = Document::parse(input);
+        assert_eq!(doc.turns[0].user.content, "Hi");
+        assert_eq!(
+            doc.turns[0].user.meta.get("time").unwrap(),
+            "2023-03-12T14:05:00"
+        );
+        assert_eq!(doc.turns[0].assistant, "Hello!");
+        assert_eq!(
+            doc.turns[0].meta.get("time").unwrap(),
+            "2023-03-12T14:06:00"
+        );
+        assert_eq!(doc.to_cmf(), input);
+    }
+
+    #[test]
+    fn test_alternates_roundtrip() {
+        let input = "> Pick a name\n<!-- cmf: alternate=1 label=short -->\nBo\n\n<!-- cmf: alternate=2 -->\n<!-- cmf: rating=5 -->\nBartholomew";
+
+        let doc = Document::parse(input);
+        let turn = &doc.turns[0];
+        assert_eq!(turn.assistant, "");
+        assert_eq!(turn.alternates.len(), 2);
+        assert_eq!(turn.alternates[0].content, "Bo");
+        assert_eq!(turn.alternates[0].meta.get("label").unwrap(), "short");
+        assert_eq!(turn.alternates[1].meta.get("rating").unwrap(), "5");
+        assert_eq!(doc.to_cmf(), input);
+
+        let canonical = turn.to_cmf();
+        assert_eq!(
+            canonical,
+            "> Pick a name\n\n<!-- cmf: alternate=1 label=short -->\nBo\n\n<!-- cmf: alternate=2 rating=5 -->\nBartholomew"
+        );
+        assert_eq!(Document::parse(&canonical).turns[0], *turn);
+    }
+
+    #[test]
+    fn test_to_cmf_escapes_assistant_blockquotes() {
+        let doc = Document {
+            turns: vec![Turn {
+                user: UserMessage {
+                    content: "Quote something".to_string(),
+                    ..Default::default()
+                },
+                assistant: "> To be or not to be".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(doc.to_cmf(), "> Quote something\n > To be or not to be");
+        assert_eq!(Document::parse(&doc.to_cmf()).turns.len(), 1);
+    }
+
+    #[test]
+    fn test_frontmatter() {
+        // Keys are written back in sorted order
+        let input =
+            "---\ndate: 2024-05-01\ntags: [infra, rust]\ntitle: Setup help\n---\n\n> Hi\nHello!";
+
+        let doc = Document::parse(input);
+        assert_eq!(doc.title(), Some("Setup help"));
+        assert_eq!(doc.date(), Some("2024-05-01"));
+        assert_eq!(doc.tags(), vec!["infra", "rust"]);
+        assert_eq!(doc.turns.len(), 1);
+        assert_eq!(doc.to_cmf(), input);
+    }
+
+    #[test]
+    fn test_meta_edit_keeps_preamble_notes() {
+        let input = "---\ntitle: Setup help\n---\n\n# Notes\nAsk about CI.\n\n> Hi\nHello!";
+        let mut doc = Document::parse(input);
+        doc.meta
+            .insert("system".to_string(), "Be brief.\nUse examples.".to_string());
+        let output = doc.to_cmf();
+        assert_eq!(
+            output,
+            "---\nsystem: |-\n  Be brief.\n  Use examples.\ntitle: Setup help\n---\n\n# Notes\nAsk about CI.\n\n> Hi\nHello!"
+        );
+        let reparsed = Document::parse(&output);
+        assert_eq!(reparsed.system(), Some("Be brief.\nUse examples."));
+        assert_eq!(reparsed.turns, doc.turns);
+
+        // Without frontmatter the notes are kept too
+        let mut doc = Document::parse("# Notes\n\n> Hi");
+        doc.meta.insert("title".to_string(), "Hi".to_string());
+        assert_eq!(doc.to_cmf(), "---\ntitle: Hi\n---\n\n# Notes\n\n> Hi");
+    }
+
     #[test]
     fn test_display_impl() {
         let doc = Document {
@@ -415,9 +824,12 @@ The answer is 4."#;
                 user: UserMessage {
                     username: None,
                     content: "Test".to_string(),
+                    ..Default::default()
                 },
                 assistant: "Response".to_string(),
+                ..Default::default()
             }],
+            ..Default::default()
         };
         assert_eq!(format!("{}", doc), "> Test\nResponse");
     }
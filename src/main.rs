@@ -1,7 +1,30 @@
-use clap::{Parser, Subcommand};
-use cmf::Document;
-use cmf::terminal_renderer::MarkdownRenderer;
+mod repl;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use cmf::backend::{self, Anthropic, Backend, Ollama, OpenAi, Params};
+use cmf::branch;
+use cmf::chat::{self, ChatError};
+use cmf::collection::Collection;
+use cmf::compact;
+use cmf::diff;
+use cmf::file::{self, Expect};
+use cmf::grep;
+use cmf::import::transcript::Pattern;
+use cmf::import::{self, ImportError};
+use cmf::merge;
+use cmf::response;
+use cmf::select::Selector;
+use cmf::split;
+use cmf::stats::{self, Stats};
+use cmf::terminal_renderer::{MarkdownRenderer, StreamRenderer};
+use cmf::tokenizer::{Encoding, Estimate, Tokenizer};
+use cmf::trim::Strategy;
+use cmf::{Document, Turn, UserMessage};
+use std::collections::BTreeMap;
 use std::fs;
+use std::io::{self, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 
 #[derive(Parser)]
@@ -19,6 +42,9 @@ enum Commands {
     Detect {
         /// Path to the markdown file
         file: String,
+        /// Read the file as a collection of `---`-separated conversations
+        #[arg(long)]
+        collection: bool,
     },
     /// Check CMF conformance
     Check {
@@ -35,12 +61,380 @@ enum Commands {
     ToOpenaiChat {
         /// Path to the markdown file
         file: String,
+        /// Read the file as a collection of `---`-separated conversations
+        #[arg(long)]
+        collection: bool,
     },
     /// Convert to OpenAI Responses API format
     #[command(name = "to-openai-responses")]
     ToOpenaiResponses {
         /// Path to the markdown file
         file: String,
+        /// Read the file as a collection of `---`-separated conversations
+        #[arg(long)]
+        collection: bool,
+    },
+    /// Export preference pairs from alternate replies as DPO-style JSONL
+    #[command(name = "to-dpo")]
+    ToDpo {
+        /// Paths to the conversations
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Read each file as a collection of `---`-separated conversations
+        #[arg(long)]
+        collection: bool,
+    },
+    /// Convert to a standalone HTML page
+    #[command(name = "to-html")]
+    ToHtml {
+        /// Path to the markdown file
+        file: String,
+        /// Page title (defaults to the frontmatter title, then the file name)
+        #[arg(long)]
+        title: Option<String>,
+    },
+    /// Build a static HTML archive from a directory of conversations
+    Site {
+        /// Directory to search for `.cmf` files
+        dir: String,
+        /// Output directory
+        #[arg(short, long, default_value = "public")]
+        output: String,
+    },
+    /// Append a message read from stdin to a conversation
+    Append {
+        /// Who the message is from
+        role: Role,
+        /// Path to the conversation (created if missing)
+        file: String,
+        /// Attribute the user message to this username
+        #[arg(long = "as", value_name = "USERNAME")]
+        username: Option<String>,
+        /// Extend the last reply instead of refusing when it already has one
+        #[arg(long = "continue")]
+        continue_reply: bool,
+    },
+    /// Append the reply from an API response or event stream on stdin
+    #[command(name = "ingest-response")]
+    IngestResponse {
+        /// Path to the conversation the request was made from
+        file: String,
+    },
+    /// Send the conversation to a model and append the reply
+    Chat {
+        /// Path to the conversation
+        file: String,
+        #[command(flatten)]
+        backend: BackendArgs,
+        /// Append this user message before sending
+        #[arg(short, long)]
+        message: Option<String>,
+    },
+    /// Replace early turns with a summary from a model
+    ///
+    /// The original conversation is saved next to the file first, as
+    /// chat-full.cmf, and the summary turn links to it.
+    Compact {
+        /// Path to the conversation
+        file: String,
+        /// Turns at the end to keep as they are
+        #[arg(long, default_value_t = 6)]
+        keep_last: usize,
+        #[command(flatten)]
+        backend: BackendArgs,
+    },
+    /// Search conversations, showing each matching turn
+    Grep {
+        /// Regular expression to search for
+        pattern: String,
+        /// Files and directories to search (directories for `.cmf` files,
+        /// recursively)
+        #[arg(default_value = ".")]
+        paths: Vec<String>,
+        /// Only search user messages or assistant replies
+        #[arg(long)]
+        role: Option<Role>,
+        /// Only search turns where this user asked
+        #[arg(long, value_name = "USERNAME")]
+        user: Option<String>,
+        /// Match case-insensitively
+        #[arg(short, long)]
+        ignore_case: bool,
+        /// Print only the matching lines, not the rest of their turns
+        #[arg(long)]
+        no_context: bool,
+    },
+    /// Select turns with a selector such as `user:alice code:rust` or `2..4`
+    ///
+    /// Terms narrow the selection left to right: positions (`3`, `-1`,
+    /// `2..4`, `first`, `last`), `tag:NAME`, `user:NAME`, `question:REGEX`,
+    /// `reply:REGEX`, `text:REGEX`, `code[:LANG]` and annotation filters like
+    /// `[score>=4]`, each negatable with `!`.
+    Query {
+        /// The selector expression
+        expr: String,
+        /// Files and directories to query (directories for `.cmf` files,
+        /// recursively)
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Print the selected turns as JSON
+        #[arg(long)]
+        json: bool,
+        /// Read each file as a collection of `---`-separated conversations
+        #[arg(long)]
+        collection: bool,
+    },
+    /// Count messages, words, tokens and code blocks
+    Stats {
+        /// Files and directories (for `.cmf` files, recursively)
+        #[arg(default_value = ".")]
+        paths: Vec<String>,
+        /// Print the counts, per file and in total, as JSON
+        #[arg(long)]
+        json: bool,
+        /// Read each file as a collection of `---`-separated conversations
+        #[arg(long)]
+        collection: bool,
+        /// Count only the turns a selector picks, such as `[score>=4]` or
+        /// `user:alice` (see `cmf query`)
+        #[arg(long, value_name = "SELECTOR")]
+        filter: Option<String>,
+    },
+    /// Count the tokens a conversation takes as a Chat Completions request
+    Tokens {
+        /// Path to the conversation
+        file: String,
+        /// BPE encoding: cl100k_base or o200k_base
+        #[arg(long, default_value = "o200k_base")]
+        encoding: String,
+        /// The `.tiktoken` vocabulary (defaults to
+        /// `$CMF_TIKTOKEN_DIR/<encoding>.tiktoken`)
+        #[arg(long)]
+        vocab: Option<String>,
+    },
+    /// Shrink a conversation to fit a context window
+    ///
+    /// Strategies run in order until it fits; the system prompt and the
+    /// final turn are always kept. Exits with 1 if it still does not fit.
+    Trim {
+        /// Path to the conversation
+        file: String,
+        /// The token budget
+        #[arg(long)]
+        max_tokens: usize,
+        /// Strategies to apply, in order
+        #[arg(long, value_enum, value_delimiter = ',', default_value = "drop-oldest")]
+        strategy: Vec<TrimStrategy>,
+        /// Turns at the start kept by keep-ends
+        #[arg(long, default_value_t = 1)]
+        keep_first: usize,
+        /// Turns at the end kept by keep-ends
+        #[arg(long, default_value_t = 1)]
+        keep_last: usize,
+        /// Report what would be dropped or shortened instead of writing
+        #[arg(long)]
+        dry_run: bool,
+        /// Write the result here instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+        /// BPE encoding: cl100k_base or o200k_base
+        #[arg(long, default_value = "o200k_base")]
+        encoding: String,
+        /// The `.tiktoken` vocabulary (defaults to
+        /// `$CMF_TIKTOKEN_DIR/<encoding>.tiktoken`)
+        #[arg(long)]
+        vocab: Option<String>,
+        /// Estimate tokens at four characters each instead of loading a
+        /// vocabulary
+        #[arg(long, conflicts_with = "vocab")]
+        estimate: bool,
+    },
+    /// Compare two conversations turn by turn
+    ///
+    /// Also works as a git external diff (seven arguments) or, with
+    /// --textconv, as a textconv filter for one file.
+    Diff {
+        /// OLD NEW, or the arguments git passes to an external diff
+        #[arg(required = true, num_args = 1..=7)]
+        files: Vec<String>,
+        /// Print the changed turns as JSON
+        #[arg(long)]
+        json: bool,
+        /// Print one file in a line-diffable form for git textconv
+        #[arg(long)]
+        textconv: bool,
+        /// Exit with status 1 when the conversations differ
+        #[arg(long)]
+        exit_code: bool,
+    },
+    /// Merge two versions of a conversation turn by turn
+    ///
+    /// Works as a git merge driver: `cmf merge %O %A %B` writes the result
+    /// over OURS and exits with status 1 when conflicts remain.
+    Merge {
+        /// The common ancestor
+        base: String,
+        /// Our version, overwritten with the result unless --output is given
+        ours: String,
+        /// Their version
+        theirs: String,
+        /// Length of the conflict markers
+        #[arg(long, default_value_t = 7)]
+        marker_size: usize,
+        /// Write the result here instead (`-` for stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Start a new branch of a conversation in a sibling file
+    Fork {
+        /// Path to the conversation
+        file: String,
+        /// Number of turns the branch keeps (defaults to all of them)
+        #[arg(long)]
+        at: Option<usize>,
+        /// Where to write the branch (defaults to `<name>-fork.cmf`)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// List the branches of a conversation
+    Branches {
+        /// Path to any branch of the conversation
+        file: String,
+    },
+    /// Split a conversation into parts, written next to it as `<name>-N.cmf`
+    Split {
+        /// Path to the conversation
+        file: String,
+        /// Turn counts after which to start a new part, e.g. `5,12`
+        #[arg(long, value_delimiter = ',', required_unless_present = "every")]
+        at: Vec<usize>,
+        /// Start a new part every N turns
+        #[arg(long, conflicts_with = "at")]
+        every: Option<NonZeroUsize>,
+        /// Record the source file and turn range in each part's frontmatter
+        #[arg(long)]
+        annotate: bool,
+    },
+    /// Join conversations into one, merging their frontmatter
+    Cat {
+        /// Conversations to join, in order
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Write the result here instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Mark the first message of each part with the file it came from
+        #[arg(long)]
+        annotate: bool,
+        /// Read collections and write one, instead of joining conversations
+        #[arg(long)]
+        collection: bool,
+    },
+    /// Chat interactively, keeping the conversation in a file
+    Repl {
+        /// Path to the conversation (created if missing)
+        file: String,
+        #[command(flatten)]
+        backend: BackendArgs,
+    },
+    /// Convert a chat export from another tool to CMF
+    Import {
+        #[command(subcommand)]
+        format: ImportFormat,
+    },
+}
+
+/// Options shared by commands that talk to a model
+#[derive(Args)]
+struct BackendArgs {
+    /// API to talk to
+    #[arg(long, value_enum, default_value_t = BackendKind::Openai)]
+    backend: BackendKind,
+    /// API base URL (defaults to the backend's public endpoint)
+    #[arg(long)]
+    base_url: Option<String>,
+    /// Model name
+    #[arg(long)]
+    model: String,
+    /// API key (defaults to $OPENAI_API_KEY or $ANTHROPIC_API_KEY)
+    #[arg(long)]
+    api_key: Option<String>,
+    /// Sampling temperature
+    #[arg(long)]
+    temperature: Option<f64>,
+    /// Maximum tokens in the reply
+    #[arg(long)]
+    max_tokens: Option<u32>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum BackendKind {
+    /// OpenAI Chat Completions and compatible servers
+    Openai,
+    Anthropic,
+    Ollama,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TrimStrategy {
+    /// Drop turns from the start
+    DropOldest,
+    /// Drop turns from the middle, keeping --keep-first and --keep-last
+    KeepEnds,
+    /// Cut the middle out of the longest replies
+    TruncateReplies,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Subcommand)]
+enum ImportFormat {
+    /// WhatsApp `.txt` chat export
+    Whatsapp {
+        /// Path to the export file
+        file: String,
+        /// Sender whose messages become assistant replies
+        #[arg(long)]
+        assistant: Option<String>,
+    },
+    /// Telegram Desktop `result.json` chat export
+    Telegram {
+        /// Path to the export file
+        file: String,
+        /// Sender whose messages become assistant replies
+        #[arg(long)]
+        assistant: Option<String>,
+    },
+    /// WebVTT transcript with `<v Speaker>` tags or `SPEAKER_00:` prefixes
+    Vtt {
+        /// Path to the transcript
+        file: String,
+        /// Speaker whose cues become assistant replies (e.g. the interviewer)
+        #[arg(long)]
+        assistant: Option<String>,
+    },
+    /// SRT transcript with `SPEAKER_00:` prefixes
+    Srt {
+        /// Path to the transcript
+        file: String,
+        /// Speaker whose cues become assistant replies (e.g. the interviewer)
+        #[arg(long)]
+        assistant: Option<String>,
+    },
+    /// Plain transcript with `## User` headings or `User:` prefixes, or an Aider chat log
+    Transcript {
+        /// Path to the transcript
+        file: String,
+        /// Custom role labels as USER/ASSISTANT, e.g. `Customer/Agent|Support`
+        #[arg(long)]
+        pattern: Option<Pattern>,
+        /// Fail unless the layout was detected with at least this confidence (0-1)
+        #[arg(long, default_value_t = 0.0)]
+        min_confidence: f64,
     },
 }
 
@@ -48,11 +442,141 @@ fn main() -> ExitCode {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Detect { file } => cmd_detect(&file),
+        Commands::Detect { file, collection } => cmd_detect(&file, collection),
         Commands::Check { file } => cmd_check(&file),
         Commands::Render { file } => cmd_render(&file),
-        Commands::ToOpenaiChat { file } => cmd_to_openai_chat(&file),
-        Commands::ToOpenaiResponses { file } => cmd_to_openai_responses(&file),
+        Commands::ToOpenaiChat { file, collection } => cmd_to_openai_chat(&file, collection),
+        Commands::ToDpo { files, collection } => cmd_to_dpo(&files, collection),
+        Commands::ToOpenaiResponses { file, collection } => {
+            cmd_to_openai_responses(&file, collection)
+        }
+        Commands::ToHtml { file, title } => cmd_to_html(&file, title.as_deref()),
+        Commands::Site { dir, output } => cmd_site(&dir, &output),
+        Commands::Append {
+            role,
+            file,
+            username,
+            continue_reply,
+        } => cmd_append(role, &file, username.as_deref(), continue_reply),
+        Commands::IngestResponse { file } => cmd_ingest_response(&file),
+        Commands::Chat {
+            file,
+            backend,
+            message,
+        } => cmd_chat(
+            &file,
+            backend.backend().as_ref(),
+            &backend.params(),
+            message.as_deref(),
+        ),
+        Commands::Grep {
+            pattern,
+            paths,
+            role,
+            user,
+            ignore_case,
+            no_context,
+        } => cmd_grep(&pattern, &paths, role, user, ignore_case, !no_context),
+        Commands::Query {
+            expr,
+            paths,
+            json,
+            collection,
+        } => cmd_query(&expr, &paths, json, collection),
+        Commands::Stats {
+            paths,
+            json,
+            collection,
+            filter,
+        } => cmd_stats(&paths, json, collection, filter.as_deref()),
+        Commands::Compact {
+            file,
+            keep_last,
+            backend,
+        } => cmd_compact(
+            &file,
+            keep_last,
+            backend.backend().as_ref(),
+            &backend.params(),
+        ),
+        Commands::Tokens {
+            file,
+            encoding,
+            vocab,
+        } => cmd_tokens(&file, &encoding, vocab.as_deref()),
+        Commands::Trim {
+            file,
+            max_tokens,
+            strategy,
+            keep_first,
+            keep_last,
+            dry_run,
+            output,
+            encoding,
+            vocab,
+            estimate,
+        } => {
+            let strategies: Vec<Strategy> = strategy
+                .iter()
+                .map(|s| match s {
+                    TrimStrategy::DropOldest => Strategy::DropOldest,
+                    TrimStrategy::KeepEnds => Strategy::KeepEnds {
+                        first: keep_first,
+                        last: keep_last,
+                    },
+                    TrimStrategy::TruncateReplies => Strategy::TruncateReplies,
+                })
+                .collect();
+            let counter = if estimate {
+                None
+            } else {
+                match load_tokenizer(&encoding, vocab.as_deref()) {
+                    Ok(tokenizer) => Some(tokenizer),
+                    Err(code) => return code,
+                }
+            };
+            cmd_trim(
+                &file,
+                max_tokens,
+                &strategies,
+                counter.as_ref(),
+                dry_run,
+                output.as_deref(),
+            )
+        }
+        Commands::Diff {
+            files,
+            json,
+            textconv,
+            exit_code,
+        } => cmd_diff(&files, json, textconv, exit_code),
+        Commands::Merge {
+            base,
+            ours,
+            theirs,
+            marker_size,
+            output,
+        } => cmd_merge(&base, &ours, &theirs, marker_size, output.as_deref()),
+        Commands::Fork { file, at, output } => cmd_fork(&file, at, output.as_deref()),
+        Commands::Branches { file } => cmd_branches(&file),
+        Commands::Split {
+            file,
+            at,
+            every,
+            annotate,
+        } => cmd_split(&file, &at, every, annotate),
+        Commands::Cat {
+            files,
+            output,
+            annotate,
+            collection,
+        } => cmd_cat(&files, output.as_deref(), annotate, collection),
+        Commands::Repl { file, backend } => repl::run(
+            Path::new(&file),
+            backend.backend().as_ref(),
+            &backend.params(),
+        ),
+        Commands::Import { format } => cmd_import(format),
     }
 }
 
@@ -63,18 +587,64 @@ fn read_file(path: &str) -> Result<String, ExitCode> {
     })
 }
 
-fn cmd_detect(file: &str) -> ExitCode {
+/// Files named directly, plus every `.cmf` file under the directories
+fn expand_paths(paths: &[String]) -> Result<Vec<String>, ExitCode> {
+    fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                walk(&path, files)?;
+            } else if path.extension().is_some_and(|ext| ext == "cmf") {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        if !Path::new(path).is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let mut found = Vec::new();
+        if let Err(e) = walk(Path::new(path), &mut found) {
+            eprintln!("error: {}: {}", path, e);
+            return Err(ExitCode::FAILURE);
+        }
+        found.sort();
+        files.extend(found.iter().map(|file| file.display().to_string()));
+    }
+    Ok(files)
+}
+
+/// The conversations in a file: one, or all of them if it is a collection
+fn read_documents(path: &str, collection: bool) -> Result<Vec<Document>, ExitCode> {
+    let content = read_file(path)?;
+    Ok(if collection {
+        Collection::parse(&content)
+    } else {
+        vec![Document::parse(&content)]
+    })
+}
+
+fn cmd_detect(file: &str, collection: bool) -> ExitCode {
     let content = match read_file(file) {
         Ok(c) => c,
         Err(code) => return code,
     };
 
-    if Document::is_valid_cmf(&content) {
+    if !Document::is_valid_cmf(&content) {
+        ExitCode::FAILURE
+    } else if collection {
+        let docs = Collection::parse(&content);
+        let turns: usize = docs.iter().map(|doc| doc.turns.len()).sum();
+        println!("{} conversations, {} turns", docs.len(), turns);
+        ExitCode::SUCCESS
+    } else {
         let doc = Document::parse(&content);
         println!("{} turns", doc.turns.len());
         ExitCode::SUCCESS
-    } else {
-        ExitCode::FAILURE
     }
 }
 
@@ -108,15 +678,23 @@ fn cmd_render(file: &str) -> ExitCode {
     ExitCode::SUCCESS
 }
 
-fn cmd_to_openai_chat(file: &str) -> ExitCode {
+fn cmd_to_openai_chat(file: &str, collection: bool) -> ExitCode {
     let content = match read_file(file) {
         Ok(c) => c,
         Err(code) => return code,
     };
 
-    let doc = Document::parse(&content);
-    let messages = doc.to_openai_chat();
-    match serde_json::to_string_pretty(&messages) {
+    // A collection becomes an array of conversations
+    let json = if collection {
+        let conversations: Vec<_> = Collection::parse(&content)
+            .iter()
+            .map(Document::to_openai_chat)
+            .collect();
+        serde_json::to_string_pretty(&conversations)
+    } else {
+        serde_json::to_string_pretty(&Document::parse(&content).to_openai_chat())
+    };
+    match json {
         Ok(json) => {
             println!("{}", json);
             ExitCode::SUCCESS
@@ -128,15 +706,47 @@ fn cmd_to_openai_chat(file: &str) -> ExitCode {
     }
 }
 
-fn cmd_to_openai_responses(file: &str) -> ExitCode {
+fn cmd_to_dpo(files: &[String], collection: bool) -> ExitCode {
+    let mut output = String::new();
+    for file in files {
+        let docs = match read_documents(file, collection) {
+            Ok(docs) => docs,
+            Err(code) => return code,
+        };
+        for example in docs.iter().flat_map(Document::to_dpo) {
+            match serde_json::to_string(&example) {
+                Ok(json) => {
+                    output.push_str(&json);
+                    output.push('\n');
+                }
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    }
+    print!("{}", output);
+    ExitCode::SUCCESS
+}
+
+fn cmd_to_openai_responses(file: &str, collection: bool) -> ExitCode {
     let content = match read_file(file) {
         Ok(c) => c,
         Err(code) => return code,
     };
 
-    let doc = Document::parse(&content);
-    let messages = doc.to_openai_responses();
-    match serde_json::to_string_pretty(&messages) {
+    // A collection becomes an array of conversations
+    let json = if collection {
+        let conversations: Vec<_> = Collection::parse(&content)
+            .iter()
+            .map(Document::to_openai_responses)
+            .collect();
+        serde_json::to_string_pretty(&conversations)
+    } else {
+        serde_json::to_string_pretty(&Document::parse(&content).to_openai_responses())
+    };
+    match json {
         Ok(json) => {
             println!("{}", json);
             ExitCode::SUCCESS
@@ -147,3 +757,944 @@ fn cmd_to_openai_responses(file: &str) -> ExitCode {
         }
     }
 }
+
+fn cmd_to_html(file: &str, title: Option<&str>) -> ExitCode {
+    let content = match read_file(file) {
+        Ok(c) => c,
+        Err(code) => return code,
+    };
+
+    let doc = Document::parse(&content);
+    let default_title = doc.title().map(str::to_string).unwrap_or_else(|| {
+        Path::new(file)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| file.to_string())
+    });
+    print!("{}", doc.to_html(title.unwrap_or(&default_title)));
+    ExitCode::SUCCESS
+}
+
+fn cmd_site(dir: &str, output: &str) -> ExitCode {
+    match cmf::site::build(Path::new(dir), Path::new(output)) {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}: {}", dir, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn read_stdin() -> Result<String, ExitCode> {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input).map_err(|e| {
+        eprintln!("error: stdin: {}", e);
+        ExitCode::FAILURE
+    })?;
+    Ok(input)
+}
+
+fn cmd_append(role: Role, file: &str, username: Option<&str>, continue_reply: bool) -> ExitCode {
+    match read_stdin() {
+        Ok(input) => append(role, file, username, &input, continue_reply),
+        Err(code) => code,
+    }
+}
+
+/// Append `input` to `file` as a message from `role`
+fn append(
+    role: Role,
+    file: &str,
+    username: Option<&str>,
+    input: &str,
+    continue_reply: bool,
+) -> ExitCode {
+    let input = input.trim_end_matches(['\n', '\r']);
+    if input.trim().is_empty() {
+        eprintln!("error: nothing to append on stdin");
+        return ExitCode::FAILURE;
+    }
+
+    match role {
+        Role::User => {
+            let turn = Turn {
+                user: UserMessage {
+                    username: username.map(str::to_string),
+                    content: input.to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            match file::append_turn(Path::new(file), &turn, Expect::Any) {
+                Ok(_) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("error: {}: {}", file, e);
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        Role::Assistant => {
+            if username.is_some() {
+                eprintln!("error: --as only applies to user messages");
+                return ExitCode::FAILURE;
+            }
+            append_reply(file, input, continue_reply)
+        }
+    }
+}
+
+/// Append `text` as the reply to the last turn of `file`
+fn append_reply(file: &str, text: &str, continue_reply: bool) -> ExitCode {
+    let content = match read_file(file) {
+        Ok(c) => c,
+        Err(code) => return code,
+    };
+
+    let mut doc = Document::parse(&content);
+    match doc.turns.last() {
+        None => {
+            eprintln!("error: {}: no user turn to reply to", file);
+            return ExitCode::FAILURE;
+        }
+        Some(turn) if !turn.assistant.is_empty() && !continue_reply => {
+            eprintln!(
+                "error: {}: last turn already has a reply (use --continue to extend it)",
+                file
+            );
+            return ExitCode::FAILURE;
+        }
+        Some(_) => {}
+    }
+    // A continuation starts a new paragraph of the existing reply
+    let text = if doc
+        .turns
+        .last()
+        .is_some_and(|turn| !turn.assistant.is_empty())
+    {
+        let separator = if content.ends_with("\n\n") {
+            ""
+        } else if content.ends_with('\n') {
+            "\n"
+        } else {
+            "\n\n"
+        };
+        format!("{}{}", separator, text)
+    } else {
+        text.to_string()
+    };
+    // Validate the combined reply before touching the file
+    if let Err(e) = doc.push_assistant(&text) {
+        eprintln!("error: {}: {}", file, e);
+        return ExitCode::FAILURE;
+    }
+
+    let expect = Expect::Len(content.len() as u64);
+    match file::append_assistant_delta(Path::new(file), &text, expect) {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}: {}", file, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn cmd_ingest_response(file: &str) -> ExitCode {
+    let input = match read_stdin() {
+        Ok(input) => input,
+        Err(code) => return code,
+    };
+
+    let reply = match response::parse(&input) {
+        Ok(reply) => reply,
+        Err(e) => {
+            eprintln!("error: stdin: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if reply.is_empty() {
+        eprintln!("error: stdin: response has no text or tool calls");
+        return ExitCode::FAILURE;
+    }
+    if reply.is_truncated() {
+        eprintln!("warning: reply was cut off at the token limit");
+    }
+
+    append_reply(file, &reply.to_markdown(), false)
+}
+
+impl BackendArgs {
+    fn backend(&self) -> Box<dyn Backend> {
+        let base_url = self.base_url.as_deref();
+        let api_key = |name: &str| self.api_key.clone().or_else(|| std::env::var(name).ok());
+        match self.backend {
+            BackendKind::Openai => {
+                let mut backend =
+                    OpenAi::new(base_url.unwrap_or(backend::openai::DEFAULT_BASE_URL));
+                if let Some(key) = api_key("OPENAI_API_KEY") {
+                    backend = backend.api_key(&key);
+                }
+                Box::new(backend)
+            }
+            BackendKind::Anthropic => {
+                let mut backend =
+                    Anthropic::new(base_url.unwrap_or(backend::anthropic::DEFAULT_BASE_URL));
+                if let Some(key) = api_key("ANTHROPIC_API_KEY") {
+                    backend = backend.api_key(&key);
+                }
+                Box::new(backend)
+            }
+            BackendKind::Ollama => Box::new(Ollama::new(
+                base_url.unwrap_or(backend::ollama::DEFAULT_BASE_URL),
+            )),
+        }
+    }
+
+    fn params(&self) -> Params {
+        Params {
+            model: self.model.clone(),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            ..Default::default()
+        }
+    }
+}
+
+fn cmd_chat(file: &str, backend: &dyn Backend, params: &Params, message: Option<&str>) -> ExitCode {
+    if let Some(message) = message {
+        let turn = Turn {
+            user: UserMessage {
+                content: message.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        if let Err(e) = file::append_turn(Path::new(file), &turn, Expect::Any) {
+            eprintln!("error: {}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    match stream_reply(Path::new(file), backend, params) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}: {}", file, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Request a reply into `path`, rendering it to the terminal as it arrives
+fn stream_reply(path: &Path, backend: &dyn Backend, params: &Params) -> Result<(), ChatError> {
+    let mut renderer = StreamRenderer::new();
+    let mut stdout = io::stdout();
+    let result = chat::reply_to_file(path, backend, params, |delta| {
+        print!("{}", renderer.push(delta));
+        let _ = stdout.flush();
+    });
+    print!("{}", renderer.finish());
+
+    if result.as_ref().is_ok_and(|reply| reply.is_truncated()) {
+        eprintln!("warning: reply was cut off at the token limit");
+    }
+    result.map(|_| ())
+}
+
+fn cmd_compact(file: &str, keep_last: usize, backend: &dyn Backend, params: &Params) -> ExitCode {
+    let content = match read_file(file) {
+        Ok(c) => c,
+        Err(code) => return code,
+    };
+    let doc = Document::parse(&content);
+
+    let path = Path::new(file);
+    let sidecar = compact::sidecar_path(path);
+    let compacted = match doc.compact(
+        keep_last,
+        backend,
+        params,
+        Some(&branch::parent_link(&sidecar, path)),
+    ) {
+        Ok(compacted) => compacted,
+        Err(e) => {
+            eprintln!("error: {}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Err(e) = fs::write(&sidecar, &content) {
+        eprintln!("error: {}: {}", sidecar.display(), e);
+        return ExitCode::FAILURE;
+    }
+    if let Err(e) = file::rewrite(path, &compacted, Expect::Len(content.len() as u64)) {
+        eprintln!("error: {}: {}", file, e);
+        return ExitCode::FAILURE;
+    }
+    println!("{}", sidecar.display());
+    ExitCode::SUCCESS
+}
+
+fn cmd_grep(
+    pattern: &str,
+    paths: &[String],
+    role: Option<Role>,
+    user: Option<String>,
+    ignore_case: bool,
+    context: bool,
+) -> ExitCode {
+    let mut search = match grep::Search::new(pattern, ignore_case) {
+        Ok(search) => search,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    search.role = role.map(|role| match role {
+        Role::User => grep::Role::User,
+        Role::Assistant => grep::Role::Assistant,
+    });
+    search.user = user;
+
+    let files = match expand_paths(paths) {
+        Ok(files) => files,
+        Err(code) => return code,
+    };
+    let use_colors = atty::is(atty::Stream::Stdout);
+    let mut found = false;
+    for file in files {
+        let content = match read_file(&file) {
+            Ok(c) => c,
+            Err(code) => return code,
+        };
+        let matches = search.find(&content);
+        if matches.is_empty() {
+            continue;
+        }
+        if context && found {
+            println!("--");
+        }
+        found = true;
+        println!(
+            "{}",
+            search.format(&file, &content, &matches, context, use_colors)
+        );
+    }
+
+    // Like grep, no match is not an error but still a failure
+    if found {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    }
+}
+
+fn cmd_query(expr: &str, paths: &[String], json: bool, collection: bool) -> ExitCode {
+    let selector: Selector = match expr.parse() {
+        Ok(selector) => selector,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let files = match expand_paths(paths) {
+        Ok(files) => files,
+        Err(code) => return code,
+    };
+
+    // Each conversation with selected turns, trimmed to them
+    let mut selected = Vec::new();
+    let mut rows = Vec::new();
+    for file in &files {
+        let docs = match read_documents(file, collection) {
+            Ok(docs) => docs,
+            Err(code) => return code,
+        };
+        for doc in docs {
+            let turns = selector.select(&doc);
+            if turns.is_empty() {
+                continue;
+            }
+            for &i in &turns {
+                let mut row = match serde_json::to_value(&doc.turns[i]) {
+                    Ok(row) => row,
+                    Err(e) => {
+                        eprintln!("error: {}", e);
+                        return ExitCode::FAILURE;
+                    }
+                };
+                row["file"] = file.as_str().into();
+                row["turn"] = (i + 1).into();
+                rows.push(row);
+            }
+            let mut part = doc.clone();
+            part.turns = turns.iter().map(|&i| doc.turns[i].clone()).collect();
+            selected.push(part);
+        }
+    }
+
+    if json {
+        match serde_json::to_string_pretty(&rows) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else if !selected.is_empty() {
+        println!("{}", Collection::to_cmf(&selected));
+    }
+    ExitCode::SUCCESS
+}
+
+fn cmd_stats(paths: &[String], json: bool, collection: bool, filter: Option<&str>) -> ExitCode {
+    let selector: Option<Selector> = match filter.map(str::parse).transpose() {
+        Ok(selector) => selector,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let files = match expand_paths(paths) {
+        Ok(files) => files,
+        Err(code) => return code,
+    };
+
+    let mut total = Stats::default();
+    let mut per_file = BTreeMap::new();
+    for file in &files {
+        let docs = match read_documents(file, collection) {
+            Ok(docs) => docs,
+            Err(code) => return code,
+        };
+        let mut stats = Stats::default();
+        for doc in docs {
+            let mut doc_stats = match &selector {
+                Some(selector) => {
+                    let turns = selector.select(&doc);
+                    if turns.is_empty() {
+                        continue;
+                    }
+                    doc.stats_for(&turns)
+                }
+                None => doc.stats(),
+            };
+            if let Some(longest) = &mut doc_stats.longest_turn {
+                longest.file = Some(file.clone());
+            }
+            stats.add(&doc_stats);
+        }
+        total.add(&stats);
+        per_file.insert(file.as_str(), stats);
+    }
+
+    if json {
+        let report = serde_json::json!({
+            "files": per_file,
+            "total": total,
+            "averages": total.averages(),
+        });
+        match serde_json::to_string_pretty(&report) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        print_stats(&total, files.len() > 1);
+    }
+    ExitCode::SUCCESS
+}
+
+fn print_stats(stats: &Stats, show_files: bool) {
+    fn counts<'a>(counts: impl Iterator<Item = (&'a str, usize)>) -> String {
+        let counts: Vec<String> = counts
+            .map(|(name, count)| {
+                let name = if name.is_empty() { "(none)" } else { name };
+                format!("{} {}", name, count)
+            })
+            .collect();
+        if counts.is_empty() {
+            "-".to_string()
+        } else {
+            counts.join(", ")
+        }
+    }
+    let roles = |field: fn(&stats::Totals) -> usize| {
+        counts(
+            stats
+                .roles
+                .iter()
+                .map(|(role, totals)| (role.as_str(), field(totals))),
+        )
+    };
+
+    println!("conversations  {}", stats.conversations);
+    println!("turns          {}", stats.turns);
+    println!("messages       {}", roles(|t| t.messages));
+    println!(
+        "usernames      {}",
+        counts(stats.usernames.iter().map(|(name, n)| (name.as_str(), *n)))
+    );
+    println!("words          {}", roles(|t| t.words));
+    println!("characters     {}", roles(|t| t.chars));
+    println!("tokens (est.)  {}", roles(|t| t.tokens));
+    println!(
+        "code blocks    {}",
+        counts(
+            stats
+                .code_blocks
+                .iter()
+                .map(|(lang, n)| (lang.as_str(), *n))
+        )
+    );
+    if let Some(longest) = &stats.longest_turn {
+        let file = match &longest.file {
+            Some(file) if show_files => format!("{} ", file),
+            _ => String::new(),
+        };
+        println!(
+            "longest turn   {}turn {} ({} words)",
+            file, longest.turn, longest.words
+        );
+    }
+    if stats.conversations > 1 {
+        let averages = stats.averages();
+        println!(
+            "averages       {:.1} turns, {:.1} words, {:.1} tokens per conversation; {:.1} words per turn",
+            averages.turns_per_conversation,
+            averages.words_per_conversation,
+            averages.tokens_per_conversation,
+            averages.words_per_turn
+        );
+    }
+}
+
+/// The tokenizer for `encoding`, from `vocab` or the default path
+fn load_tokenizer(encoding: &str, vocab: Option<&str>) -> Result<Tokenizer, ExitCode> {
+    let encoding: Encoding = encoding.parse().map_err(|e| {
+        eprintln!("error: {}", e);
+        ExitCode::FAILURE
+    })?;
+    let tokenizer = match vocab {
+        Some(path) => Tokenizer::load(encoding, Path::new(path)),
+        None => Tokenizer::open(encoding),
+    };
+    tokenizer.map_err(|e| {
+        eprintln!("error: {}", e);
+        ExitCode::FAILURE
+    })
+}
+
+fn cmd_tokens(file: &str, encoding: &str, vocab: Option<&str>) -> ExitCode {
+    let content = match read_file(file) {
+        Ok(c) => c,
+        Err(code) => return code,
+    };
+    let tokenizer = match load_tokenizer(encoding, vocab) {
+        Ok(tokenizer) => tokenizer,
+        Err(code) => return code,
+    };
+    let doc = Document::parse(&content);
+    println!("{} tokens", doc.token_count(&tokenizer));
+    ExitCode::SUCCESS
+}
+
+/// Fit `file` into `max_tokens`, counting with `tokenizer` or estimating
+fn cmd_trim(
+    file: &str,
+    max_tokens: usize,
+    strategies: &[Strategy],
+    tokenizer: Option<&Tokenizer>,
+    dry_run: bool,
+    output: Option<&str>,
+) -> ExitCode {
+    let content = match read_file(file) {
+        Ok(c) => c,
+        Err(code) => return code,
+    };
+    let doc = Document::parse(&content);
+    let fit = match tokenizer {
+        Some(tokenizer) => doc.fit_to_budget(max_tokens, strategies, tokenizer),
+        None => doc.fit_to_budget(max_tokens, strategies, &Estimate),
+    };
+
+    if dry_run {
+        let turns = |indices: &[usize]| -> String {
+            indices
+                .iter()
+                .map(|i| (i + 1).to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        println!(
+            "{}: {} -> {} tokens (budget {})",
+            file, fit.tokens_before, fit.tokens, max_tokens
+        );
+        if !fit.dropped.is_empty() {
+            println!("dropped turns: {}", turns(&fit.dropped));
+        }
+        if !fit.truncated.is_empty() {
+            println!("shortened replies: {}", turns(&fit.truncated));
+        }
+        if !fit.fits {
+            println!("does not fit");
+        }
+    } else {
+        let text = fit.doc.to_cmf();
+        match output {
+            Some(path) => {
+                if let Err(e) = fs::write(path, format!("{}\n", text)) {
+                    eprintln!("error: {}: {}", path, e);
+                    return ExitCode::FAILURE;
+                }
+            }
+            None => println!("{}", text),
+        }
+        if !fit.fits {
+            eprintln!(
+                "error: {}: {} tokens left, over the budget of {}",
+                file, fit.tokens, max_tokens
+            );
+        }
+    }
+
+    if fit.fits {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    }
+}
+
+fn cmd_diff(files: &[String], json: bool, textconv: bool, exit_code: bool) -> ExitCode {
+    if textconv {
+        let [file] = files else {
+            eprintln!("error: --textconv takes one file");
+            return ExitCode::FAILURE;
+        };
+        return match read_file(file) {
+            Ok(content) => {
+                print!("{}", diff::textconv(&Document::parse(&content)));
+                ExitCode::SUCCESS
+            }
+            Err(code) => code,
+        };
+    }
+
+    // git runs an external diff as `path old-file old-hex old-mode new-file new-hex new-mode`
+    let (header, old_file, new_file) = match files {
+        [old, new] => (None, old, new),
+        [path, old, _, _, new, _, _] => (Some(path), old, new),
+        _ => {
+            eprintln!("error: expected two files to compare");
+            return ExitCode::FAILURE;
+        }
+    };
+    let old = match read_file(old_file) {
+        Ok(c) => Document::parse(&c),
+        Err(code) => return code,
+    };
+    let new = match read_file(new_file) {
+        Ok(c) => Document::parse(&c),
+        Err(code) => return code,
+    };
+
+    let diffs: Vec<_> = diff::diff(&old, &new)
+        .into_iter()
+        .filter(|d| d.change != diff::Change::Unchanged)
+        .collect();
+    if json {
+        match serde_json::to_string_pretty(&diffs) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else if !diffs.is_empty() {
+        let use_colors = atty::is(atty::Stream::Stdout);
+        if let Some(path) = header {
+            println!("cmf diff {}", path);
+        }
+        println!("{}", diff::format(&diffs, &old, &new, use_colors));
+    }
+
+    if exit_code && !diffs.is_empty() {
+        ExitCode::from(1)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn cmd_merge(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    marker_size: usize,
+    output: Option<&str>,
+) -> ExitCode {
+    let mut docs = Vec::new();
+    for file in [base, ours, theirs] {
+        match read_file(file) {
+            Ok(content) => docs.push(Document::parse(&content)),
+            Err(code) => return code,
+        }
+    }
+    let result = merge::merge(&docs[0], &docs[1], &docs[2], marker_size);
+
+    let target = output.unwrap_or(ours);
+    if target == "-" {
+        print!("{}", result.text);
+    } else if let Err(e) = fs::write(target, &result.text) {
+        eprintln!("error: {}: {}", target, e);
+        return ExitCode::FAILURE;
+    }
+
+    if result.conflicts > 0 {
+        eprintln!("{}: {} conflict(s)", target, result.conflicts);
+        ExitCode::from(1)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn cmd_fork(file: &str, at: Option<usize>, output: Option<&str>) -> ExitCode {
+    let content = match read_file(file) {
+        Ok(c) => c,
+        Err(code) => return code,
+    };
+    let doc = Document::parse(&content);
+
+    let parent = Path::new(file);
+    let target = output.map_or_else(|| branch::fork_path(parent), PathBuf::from);
+    if target.exists() {
+        eprintln!("error: {}: already exists", target.display());
+        return ExitCode::FAILURE;
+    }
+    let fork = match doc.fork(
+        at.unwrap_or(doc.turns.len()),
+        &branch::parent_link(parent, &target),
+    ) {
+        Ok(fork) => fork,
+        Err(e) => {
+            eprintln!("error: {}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Err(e) = file::rewrite(&target, &fork, Expect::Len(0)) {
+        eprintln!("error: {}: {}", target.display(), e);
+        return ExitCode::FAILURE;
+    }
+    println!("{}", target.display());
+    ExitCode::SUCCESS
+}
+
+fn cmd_branches(file: &str) -> ExitCode {
+    let branches = match branch::branches(Path::new(file)) {
+        Ok(branches) => branches,
+        Err(e) => {
+            eprintln!("error: {}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for branch in branches {
+        let marker = if branch::same_file(&branch.path, Path::new(file)) {
+            "*"
+        } else {
+            " "
+        };
+        let origin = if branch.depth == 0 {
+            String::new()
+        } else {
+            format!(", forked after turn {}", branch.forked_at)
+        };
+        println!(
+            "{} {}{} ({} turns{})",
+            marker,
+            "  ".repeat(branch.depth),
+            branch.path.display(),
+            branch.turns,
+            origin
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+fn cmd_split(file: &str, at: &[usize], every: Option<NonZeroUsize>, annotate: bool) -> ExitCode {
+    let content = match read_file(file) {
+        Ok(c) => c,
+        Err(code) => return code,
+    };
+    let doc = Document::parse(&content);
+
+    let path = Path::new(file);
+    let source = annotate.then(|| branch::parent_link(path, path));
+    let parts = match every {
+        Some(every) => doc.split_every(every.get(), source.as_deref()),
+        None => doc.split(at, source.as_deref()),
+    };
+    let parts = match parts {
+        Ok(parts) => parts,
+        Err(e) => {
+            eprintln!("error: {}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "conversation".to_string());
+    let targets: Vec<PathBuf> = (1..=parts.len())
+        .map(|n| path.with_file_name(format!("{}-{}.cmf", stem, n)))
+        .collect();
+    if let Some(target) = targets.iter().find(|target| target.exists()) {
+        eprintln!("error: {}: already exists", target.display());
+        return ExitCode::FAILURE;
+    }
+    for (part, target) in parts.iter().zip(&targets) {
+        if let Err(e) = file::rewrite(target, part, Expect::Len(0)) {
+            eprintln!("error: {}: {}", target.display(), e);
+            return ExitCode::FAILURE;
+        }
+        println!("{}", target.display());
+    }
+    ExitCode::SUCCESS
+}
+
+fn cmd_cat(files: &[String], output: Option<&str>, annotate: bool, collection: bool) -> ExitCode {
+    let mut joined = Document::default();
+    let mut bundled = Vec::new();
+    for file in files {
+        let docs = match read_documents(file, collection) {
+            Ok(docs) => docs,
+            Err(code) => return code,
+        };
+        let source = annotate.then_some(file.as_str());
+        for doc in docs {
+            if collection {
+                bundled.push(split::concat([(&doc, source)]));
+            } else {
+                joined.append_document(&doc, source);
+            }
+        }
+    }
+
+    let text = if collection {
+        Collection::to_cmf(&bundled)
+    } else {
+        joined.to_cmf()
+    };
+    match output {
+        Some(path) => {
+            if let Err(e) = fs::write(path, format!("{}\n", text)) {
+                eprintln!("error: {}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        }
+        None => println!("{}", text),
+    }
+    ExitCode::SUCCESS
+}
+
+fn cmd_import(format: ImportFormat) -> ExitCode {
+    type Importer = Box<dyn Fn(&str) -> Result<Document, ImportError>>;
+    let (file, importer): (String, Importer) = match format {
+        ImportFormat::Whatsapp { file, assistant } => (
+            file,
+            Box::new(move |c| import::whatsapp::parse(c, assistant.as_deref())),
+        ),
+        ImportFormat::Telegram { file, assistant } => (
+            file,
+            Box::new(move |c| import::telegram::parse(c, assistant.as_deref())),
+        ),
+        ImportFormat::Vtt { file, assistant } => (
+            file,
+            Box::new(move |c| import::subtitles::parse_vtt(c, assistant.as_deref())),
+        ),
+        ImportFormat::Srt { file, assistant } => (
+            file,
+            Box::new(move |c| import::subtitles::parse_srt(c, assistant.as_deref())),
+        ),
+        ImportFormat::Transcript {
+            file,
+            pattern,
+            min_confidence,
+        } => (
+            file,
+            Box::new(move |c| {
+                let transcript = import::transcript::parse(c, pattern.as_ref())?;
+                eprintln!(
+                    "detected {} layout (confidence {:.2})",
+                    transcript.layout, transcript.confidence
+                );
+                if transcript.confidence < min_confidence {
+                    return Err(ImportError::Format(format!(
+                        "confidence below {:.2}",
+                        min_confidence
+                    )));
+                }
+                Ok(transcript.document)
+            }),
+        ),
+    };
+
+    let content = match read_file(&file) {
+        Ok(c) => c,
+        Err(code) => return code,
+    };
+
+    match importer(&content) {
+        Ok(doc) => {
+            println!("{}", doc.to_cmf());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {}: {}", file, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, content: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("cmf-main-{}-{}.cmf", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_append() {
+        let path = temp_file("append", "");
+        let file = path.to_str().unwrap();
+
+        let code = append(Role::User, file, Some("alice"), "What is 2+2?\n", false);
+        assert_eq!(code, ExitCode::SUCCESS);
+        let code = append(Role::Assistant, file, None, "4\n", false);
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "> @alice: What is 2+2?\n4"
+        );
+
+        // A second reply is refused unless it continues the first
+        let code = append(Role::Assistant, file, None, "5", false);
+        assert_eq!(code, ExitCode::FAILURE);
+        let code = append(Role::Assistant, file, None, "More\n", true);
+        assert_eq!(code, ExitCode::SUCCESS);
+        let doc = Document::parse(&fs::read_to_string(&path).unwrap());
+        assert_eq!(doc.turns[0].assistant, "4\n\nMore");
+
+        let code = append(Role::Assistant, file, Some("bob"), "Hi", true);
+        assert_eq!(code, ExitCode::FAILURE);
+        let code = append(Role::User, file, None, "\n", false);
+        assert_eq!(code, ExitCode::FAILURE);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,373 @@
+//! Three-way merge of conversations, turn by turn
+//!
+//! Each side is compared with the base to find the runs of turns it
+//! replaced. Runs that only one side touched are taken from that side;
+//! where both sides touched the same turns, turns both sides appended are
+//! all kept and turns both sides edited are merged field by field (user
+//! message, reply, annotations, alternates). Only a field changed
+//! differently on each side is a conflict, and conflict markers surround
+//! just the turns involved.
+
+use std::ops::Range;
+
+use crate::diff::{lcs, similar};
+use crate::{Document, Meta, Turn};
+
+/// The result of a merge
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merge {
+    /// The merged file, with conflict markers if `conflicts > 0`
+    pub text: String,
+    /// Number of conflicting regions
+    pub conflicts: usize,
+}
+
+enum Piece {
+    Turn(Turn),
+    Conflict(Vec<Turn>, Vec<Turn>),
+}
+
+/// Merge `ours` and `theirs`, which both started from `base`
+///
+/// `marker_size` is the length of the conflict markers (git uses 7).
+pub fn merge(base: &Document, ours: &Document, theirs: &Document, marker_size: usize) -> Merge {
+    let sides = [Side::new(base, ours), Side::new(base, theirs)];
+
+    // Group the hunks of both sides into clusters of overlapping base ranges
+    let mut hunks: Vec<(usize, &Hunk)> = sides
+        .iter()
+        .enumerate()
+        .flat_map(|(side, s)| s.hunks.iter().map(move |hunk| (side, hunk)))
+        .collect();
+    hunks.sort_by_key(|(_, hunk)| (hunk.base.start, hunk.base.end));
+    let mut clusters: Vec<Cluster> = Vec::new();
+    for (side, hunk) in hunks {
+        match clusters.last_mut() {
+            Some((range, members))
+                if hunk.base.start < range.end || hunk.base.start == range.start =>
+            {
+                range.end = range.end.max(hunk.base.end);
+                members.push((side, hunk));
+            }
+            _ => clusters.push((hunk.base.clone(), vec![(side, hunk)])),
+        }
+    }
+
+    let mut pieces = Vec::new();
+    let mut pos = 0;
+    for (range, members) in clusters {
+        pieces.extend(
+            base.turns[pos..range.start]
+                .iter()
+                .cloned()
+                .map(Piece::Turn),
+        );
+        let [ours_range, theirs_range] = [0, 1].map(|side| {
+            let hunks: Vec<&Hunk> = members
+                .iter()
+                .filter(|(s, _)| *s == side)
+                .map(|(_, hunk)| *hunk)
+                .collect();
+            sides[side].range(&range, &hunks)
+        });
+        pieces.extend(merge_chunk(
+            &base.turns[range.clone()],
+            &ours.turns[ours_range],
+            &theirs.turns[theirs_range],
+        ));
+        pos = range.end;
+    }
+    pieces.extend(base.turns[pos..].iter().cloned().map(Piece::Turn));
+
+    let (meta, mut conflicts) = match merge_meta(&base.meta, &ours.meta, &theirs.meta) {
+        Some(meta) => (meta, 0),
+        None => (ours.meta.clone(), 1),
+    };
+    let head = Document {
+        meta,
+        turns: Vec::new(),
+        preamble: ours.preamble.clone(),
+    };
+
+    let mut parts = Vec::new();
+    let head = head.to_cmf();
+    if conflicts > 0 {
+        let theirs_head = Document {
+            meta: theirs.meta.clone(),
+            ..Default::default()
+        };
+        parts.push(conflict(&head, &theirs_head.to_cmf(), marker_size));
+    } else if !head.is_empty() {
+        parts.push(head);
+    }
+    for piece in pieces {
+        match piece {
+            Piece::Turn(turn) => parts.push(render(&[turn])),
+            Piece::Conflict(ours, theirs) => {
+                conflicts += 1;
+                parts.push(conflict(&render(&ours), &render(&theirs), marker_size));
+            }
+        }
+    }
+
+    let mut text = parts.join("\n\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    Merge { text, conflicts }
+}
+
+/// A range of base turns with the hunks of either side (0 ours, 1 theirs)
+/// touching it
+type Cluster<'a> = (Range<usize>, Vec<(usize, &'a Hunk)>);
+
+/// A run of base turns one side replaced with other turns
+struct Hunk {
+    base: Range<usize>,
+    side: Range<usize>,
+}
+
+/// How one side changed the base
+struct Side {
+    hunks: Vec<Hunk>,
+    /// For each base turn, the index of the identical turn on this side
+    matched: Vec<Option<usize>>,
+    len: usize,
+}
+
+impl Side {
+    fn new(base: &Document, side: &Document) -> Self {
+        let (a, b) = (&base.turns, &side.turns);
+        let pairs = lcs(a.len(), b.len(), |i, j| a[i] == b[j]);
+
+        let mut matched = vec![None; a.len()];
+        let mut hunks = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        for (x, y) in pairs.into_iter().chain([(a.len(), b.len())]) {
+            if i < x || j < y {
+                hunks.push(Hunk {
+                    base: i..x,
+                    side: j..y,
+                });
+            }
+            if x < a.len() {
+                matched[x] = Some(y);
+            }
+            (i, j) = (x + 1, y + 1);
+        }
+        Side {
+            hunks,
+            matched,
+            len: b.len(),
+        }
+    }
+
+    /// The turns on this side that correspond to the base `range`, given
+    /// this side's hunks within it
+    fn range(&self, range: &Range<usize>, hunks: &[&Hunk]) -> Range<usize> {
+        let start = match hunks.iter().find(|hunk| hunk.base.start == range.start) {
+            Some(hunk) => hunk.side.start,
+            None => self
+                .matched
+                .get(range.start)
+                .map_or(self.len, |j| j.unwrap()),
+        };
+        let end = match hunks.iter().rev().find(|hunk| hunk.base.end == range.end) {
+            Some(hunk) => hunk.side.end,
+            None if range.end > range.start => self.matched[range.end - 1].unwrap() + 1,
+            None => start,
+        };
+        start..end
+    }
+}
+
+fn merge_chunk(base: &[Turn], ours: &[Turn], theirs: &[Turn]) -> Vec<Piece> {
+    let turns = |turns: &[Turn]| turns.iter().cloned().map(Piece::Turn).collect();
+    if ours == base {
+        return turns(theirs);
+    }
+    if theirs == base || ours == theirs {
+        return turns(ours);
+    }
+
+    // Edits to the base turns, possibly followed by appended turns
+    let edited = base.len();
+    let aligned = ours.len() >= edited
+        && theirs.len() >= edited
+        && (0..edited).all(|k| similar(&base[k], &ours[k]) && similar(&base[k], &theirs[k]));
+    if !aligned {
+        return vec![Piece::Conflict(ours.to_vec(), theirs.to_vec())];
+    }
+
+    let mut pieces: Vec<Piece> = (0..edited)
+        .map(|k| match merge_turn(&base[k], &ours[k], &theirs[k]) {
+            Some(turn) => Piece::Turn(turn),
+            None => Piece::Conflict(vec![ours[k].clone()], vec![theirs[k].clone()]),
+        })
+        .collect();
+    pieces.extend(
+        append_both(&ours[edited..], &theirs[edited..])
+            .into_iter()
+            .map(Piece::Turn),
+    );
+    pieces
+}
+
+/// Keep the turns both sides appended: shared ones once, then in time order
+/// when every turn has a `time`, else ours before theirs
+fn append_both(ours: &[Turn], theirs: &[Turn]) -> Vec<Turn> {
+    let shared = ours.iter().zip(theirs).take_while(|(a, b)| a == b).count();
+    let mut turns = ours[..shared].to_vec();
+    let (ours, theirs) = (&ours[shared..], &theirs[shared..]);
+
+    let time = |turn: &Turn| turn.user.meta.get("time").cloned();
+    let mut rest: Vec<&Turn> = ours.iter().chain(theirs).collect();
+    if rest.iter().all(|turn| time(turn).is_some()) {
+        // Stable, so ours stays first among equal times
+        rest.sort_by_key(|turn| time(turn));
+    }
+    turns.extend(rest.into_iter().cloned());
+    turns
+}
+
+/// Merge a turn field by field; `None` if a field conflicts
+fn merge_turn(base: &Turn, ours: &Turn, theirs: &Turn) -> Option<Turn> {
+    let user = pick(&base.user, &ours.user, &theirs.user)?;
+    let assistant = pick(&base.assistant, &ours.assistant, &theirs.assistant)?;
+    let alternates = pick(&base.alternates, &ours.alternates, &theirs.alternates)?;
+    let meta = merge_meta(&base.meta, &ours.meta, &theirs.meta)?;
+
+    let turn = Turn {
+        user: user.clone(),
+        assistant: assistant.clone(),
+        meta,
+        alternates: alternates.clone(),
+        ..Default::default()
+    };
+    // Keep the source formatting of a side the result is identical to
+    Some(if turn == *ours {
+        ours.clone()
+    } else if turn == *theirs {
+        theirs.clone()
+    } else {
+        turn
+    })
+}
+
+fn pick<'a, T: PartialEq>(base: &'a T, ours: &'a T, theirs: &'a T) -> Option<&'a T> {
+    if ours == base {
+        Some(theirs)
+    } else if theirs == base || ours == theirs {
+        Some(ours)
+    } else {
+        None
+    }
+}
+
+/// Merge metadata key by key; `None` if a key conflicts
+fn merge_meta(base: &Meta, ours: &Meta, theirs: &Meta) -> Option<Meta> {
+    let mut merged = Meta::new();
+    for key in base.keys().chain(ours.keys()).chain(theirs.keys()) {
+        if let Some(value) = pick(&base.get(key), &ours.get(key), &theirs.get(key))? {
+            merged.insert(key.clone(), value.to_string());
+        }
+    }
+    Some(merged)
+}
+
+fn render(turns: &[Turn]) -> String {
+    Document {
+        turns: turns.to_vec(),
+        ..Default::default()
+    }
+    .to_cmf()
+}
+
+fn conflict(ours: &str, theirs: &str, marker_size: usize) -> String {
+    let mut text = format!("{} ours\n", "<".repeat(marker_size));
+    if !ours.is_empty() {
+        text.push_str(ours);
+        text.push('\n');
+    }
+    text.push_str(&"=".repeat(marker_size));
+    text.push('\n');
+    if !theirs.is_empty() {
+        text.push_str(theirs);
+        text.push('\n');
+    }
+    text.push_str(&format!("{} theirs", ">".repeat(marker_size)));
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merged(base: &str, ours: &str, theirs: &str) -> Merge {
+        merge(
+            &Document::parse(base),
+            &Document::parse(ours),
+            &Document::parse(theirs),
+            7,
+        )
+    }
+
+    #[test]
+    fn test_both_append() {
+        let base = "> Hi\nHello";
+        let result = merged(
+            base,
+            "> Hi\nHello\n\n> @alice: Question A\nAnswer A",
+            "> Hi\nHello\n\n> @bob: Question B\nAnswer B",
+        );
+        assert_eq!(result.conflicts, 0);
+        assert_eq!(
+            result.text,
+            "> Hi\nHello\n\n> @alice: Question A\nAnswer A\n\n> @bob: Question B\nAnswer B\n"
+        );
+    }
+
+    #[test]
+    fn test_appends_in_time_order() {
+        let base = "> Hi\nHello";
+        let result = merged(
+            base,
+            "> Hi\nHello\n\n> Later\n> <!-- cmf: time=2024-05-01T10:05:00 -->",
+            "> Hi\nHello\n\n> Earlier\n> <!-- cmf: time=2024-05-01T10:00:00 -->",
+        );
+        let doc = Document::parse(&result.text);
+        let users: Vec<&str> = doc.turns.iter().map(|t| t.user.content.as_str()).collect();
+        assert_eq!(users, vec!["Hi", "Earlier", "Later"]);
+    }
+
+    #[test]
+    fn test_fields_merge_and_conflict() {
+        let base = "---\ntitle: Notes\n---\n\n> What is 2+2?\nFour\n\n> Thanks\nSure";
+        // Ours fixes the question, theirs annotates the reply and edits the last turn
+        let result = merged(
+            base,
+            "---\ntitle: Notes\n---\n\n> What is 2 + 2?\nFour\n\n> Thanks\nAnytime",
+            "---\ntitle: Notes\ntags: [math]\n---\n\n> What is 2+2?\n<!-- cmf: label=good -->\nFour\n\n> Thanks\nYou're welcome",
+        );
+        assert_eq!(result.conflicts, 1);
+        assert_eq!(
+            result.text,
+            "---\ntags: [math]\ntitle: Notes\n---\n\n\
+             > What is 2 + 2?\n<!-- cmf: label=good -->\nFour\n\n\
+             <<<<<<< ours\n> Thanks\nAnytime\n=======\n> Thanks\nYou're welcome\n>>>>>>> theirs\n"
+        );
+    }
+
+    #[test]
+    fn test_one_sided_edits() {
+        let base = "> A\n1\n\n> B\n2";
+        let result = merged(base, "> A\n1", "> A\none\n\n> B\n2");
+        assert_eq!(result.conflicts, 0);
+        assert_eq!(result.text, "> A\none\n");
+
+        // Neighbouring turns edited on different sides do not conflict
+        let result = merged(base, "> A\none\n\n> B\n2", "> A\n1\n\n> B\ntwo");
+        assert_eq!(result.conflicts, 0);
+        assert_eq!(result.text, "> A\none\n\n> B\ntwo\n");
+    }
+}
@@ -0,0 +1,400 @@
+//! Metadata for documents and messages
+//!
+//! Per-message metadata lives in `<!-- cmf: key=value -->` comments. HTML
+//! comments are invisible when a CMF file is rendered as CommonMark, so they
+//! carry metadata (timestamps, sources, ...) without polluting content.
+//!
+//! Document metadata lives in a YAML-style frontmatter block at the top of
+//! the file. Only flat `key: value` pairs are supported; lists may be written
+//! inline (`tags: [a, b]`) or as `- item` lines and are stored inline.
+//! Values spanning several lines are written as `|` block scalars.
+
+use std::collections::BTreeMap;
+
+/// Key/value metadata attached to a document or message
+pub type Meta = BTreeMap<String, String>;
+
+const COMMENT_PREFIX: &str = "<!-- cmf:";
+const COMMENT_SUFFIX: &str = "-->";
+
+/// Parse a line consisting solely of a `<!-- cmf: ... -->` comment
+pub fn parse_comment(line: &str) -> Option<Meta> {
+    let body = line
+        .trim()
+        .strip_prefix(COMMENT_PREFIX)?
+        .strip_suffix(COMMENT_SUFFIX)?;
+
+    let mut meta = Meta::new();
+    let mut chars = body.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && !c.is_whitespace()) {
+            key.push(c);
+        }
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => value.extend(chars.next().map(unescape)),
+                        _ => value.push(c),
+                    }
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    value.push(c);
+                }
+            }
+        }
+
+        if !key.is_empty() {
+            meta.insert(key, value);
+        }
+    }
+
+    Some(meta)
+}
+
+/// Format metadata as a single `<!-- cmf: ... -->` comment line
+pub fn format_comment(meta: &Meta) -> String {
+    let mut output = String::from(COMMENT_PREFIX);
+    for (key, value) in meta {
+        output.push(' ');
+        output.push_str(key);
+        output.push('=');
+        output.push_str(&format_value(value));
+    }
+    output.push(' ');
+    output.push_str(COMMENT_SUFFIX);
+    output
+}
+
+/// Parse a frontmatter block from the start of `input`
+///
+/// Returns the metadata and the number of lines the block spans, including
+/// both `---` fences.
+pub fn parse_frontmatter(input: &str) -> Option<(Meta, usize)> {
+    let mut lines = input.lines();
+    if lines.next()?.trim_end() != "---" {
+        return None;
+    }
+
+    let mut meta = Meta::new();
+    let mut list_key: Option<String> = None;
+    let mut list_items: Vec<String> = Vec::new();
+    let mut block: Option<Block> = None;
+
+    for (i, line) in lines.enumerate() {
+        if let Some(current) = block.as_mut() {
+            if current.push(line) {
+                continue;
+            }
+            let (key, value) = block.take().unwrap().finish();
+            meta.insert(key, value);
+        }
+
+        let is_item = line.trim_start().starts_with("- ");
+        if !is_item {
+            if let Some(key) = list_key.take() {
+                meta.insert(key, format!("[{}]", list_items.join(", ")));
+                list_items.clear();
+            }
+        }
+
+        if line.trim_end() == "---" {
+            return Some((meta, i + 2));
+        } else if line.starts_with('>') {
+            // A user line: the opening `---` was a thematic break
+            return None;
+        } else if is_item {
+            let item = line.trim_start()[2..].trim();
+            list_items.push(unquote(item));
+        } else if let Some((key, value)) = line.split_once(':') {
+            let key = key.trim().to_string();
+            let value = value.trim();
+            if value.is_empty() {
+                list_key = Some(key);
+            } else if let Some(started) = Block::start(&key, value) {
+                block = Some(started);
+            } else {
+                meta.insert(key, unquote(value));
+            }
+        }
+    }
+
+    // No closing fence: this was a thematic break, not frontmatter
+    None
+}
+
+/// Format metadata as a frontmatter block, fences included
+pub fn format_frontmatter(meta: &Meta) -> String {
+    let mut output = String::from("---\n");
+    for (key, value) in meta {
+        output.push_str(key);
+        output.push_str(": ");
+        if value.contains('\n') {
+            output.push_str(&format_block(value));
+            continue;
+        } else if needs_yaml_quotes(value) {
+            output.push('"');
+            output.push_str(&value.replace('\\', "\\\\").replace('"', "\\\""));
+            output.push('"');
+        } else {
+            output.push_str(value);
+        }
+        output.push('\n');
+    }
+    output.push_str("---");
+    output
+}
+
+/// A `key: |` block scalar being read, one indented line per value line
+struct Block {
+    key: String,
+    /// `-` drops the final line break, `+` keeps trailing blank lines, and
+    /// anything else keeps a single line break
+    chomp: char,
+    /// Taken from the first non-blank line unless the header gives it
+    indent: Option<usize>,
+    lines: Vec<String>,
+}
+
+impl Block {
+    /// Start a block if `value` is a header such as `|`, `|-` or `|2+`
+    fn start(key: &str, value: &str) -> Option<Block> {
+        let header = value.strip_prefix('|')?;
+        if !header
+            .chars()
+            .all(|c| c == '-' || c == '+' || c.is_ascii_digit())
+        {
+            return None;
+        }
+        Some(Block {
+            key: key.to_string(),
+            chomp: header
+                .chars()
+                .find(|c| *c == '-' || *c == '+')
+                .unwrap_or(' '),
+            indent: header
+                .chars()
+                .find(|c| c.is_ascii_digit())
+                .and_then(|c| c.to_digit(10))
+                .map(|n| n as usize),
+            lines: Vec::new(),
+        })
+    }
+
+    /// Take `line` if it belongs to the block
+    fn push(&mut self, line: &str) -> bool {
+        if line.trim().is_empty() {
+            self.lines.push(String::new());
+            return true;
+        }
+        let width = line.len() - line.trim_start_matches(' ').len();
+        let indent = *self.indent.get_or_insert(width);
+        if indent == 0 || width < indent {
+            return false;
+        }
+        self.lines.push(line[indent..].to_string());
+        true
+    }
+
+    fn finish(mut self) -> (String, String) {
+        let blank = self.lines.iter().rev().take_while(|l| l.is_empty()).count();
+        self.lines.truncate(self.lines.len() - blank);
+        let mut value = self.lines.join("\n");
+        match self.chomp {
+            '-' => {}
+            '+' => value.push_str(&"\n".repeat(blank + 1)),
+            _ => value.push('\n'),
+        }
+        (self.key, value)
+    }
+}
+
+/// Format a multi-line value as a block scalar, header included
+fn format_block(value: &str) -> String {
+    let body = value.trim_end_matches('\n');
+    let chomp = match value.len() - body.len() {
+        0 => "-",
+        1 => "",
+        _ => "+",
+    };
+    // Leading spaces would be taken for indentation
+    let indent = if body.starts_with(' ') { "2" } else { "" };
+    let mut output = format!("|{}{}\n", indent, chomp);
+    for line in body.split('\n') {
+        if !line.is_empty() {
+            output.push_str("  ");
+            output.push_str(line);
+        }
+        output.push('\n');
+    }
+    for _ in 1..value.len() - body.len() {
+        output.push('\n');
+    }
+    output
+}
+
+/// Split an inline list (`[a, b]` or `a, b`) into its items
+pub fn parse_list(value: &str) -> Vec<String> {
+    let value = value.trim();
+    let value = value
+        .strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .unwrap_or(value);
+    value
+        .split(',')
+        .map(|item| unquote(item.trim()))
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn unquote(value: &str) -> String {
+    if let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        let mut output = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                output.extend(chars.next());
+            } else {
+                output.push(c);
+            }
+        }
+        return output;
+    }
+    if let Some(inner) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        return inner.replace("''", "'");
+    }
+    value.to_string()
+}
+
+fn needs_yaml_quotes(value: &str) -> bool {
+    // Inline lists are written as-is so they stay lists
+    if value.starts_with('[') && value.ends_with(']') {
+        return false;
+    }
+    value.is_empty()
+        || value.contains(": ")
+        || value.contains(" #")
+        || value.starts_with(|c: char| "{\"'&*!|>%@`-#".contains(c) || c.is_whitespace())
+        || value.ends_with(char::is_whitespace)
+}
+
+fn format_value(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '\\' || c == '=')
+        || value.contains(COMMENT_SUFFIX);
+
+    if !needs_quotes {
+        return value.to_string();
+    }
+
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            // Keep the comment on one line
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            _ => {
+                if c == '"' || c == '\\' || (c == '>' && quoted.ends_with("--")) {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The character an escape in a quoted comment value stands for
+fn unescape(c: char) -> char {
+    match c {
+        'n' => '\n',
+        'r' => '\r',
+        c => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_comment() {
+        let meta = parse_comment("<!-- cmf: time=2023-03-12T14:05:00 source=whatsapp -->").unwrap();
+        assert_eq!(meta.get("time").unwrap(), "2023-03-12T14:05:00");
+        assert_eq!(meta.get("source").unwrap(), "whatsapp");
+    }
+
+    #[test]
+    fn test_parse_not_a_comment() {
+        assert!(parse_comment("<!-- just a comment -->").is_none());
+        assert!(parse_comment("Some text").is_none());
+    }
+
+    #[test]
+    fn test_frontmatter() {
+        let input = "---\ntitle: \"Deploys: a retro\"\ndate: 2024-05-01\ntags:\n  - infra\n  - rust\n---\n> Hi";
+        let (meta, lines) = parse_frontmatter(input).unwrap();
+        assert_eq!(lines, 7);
+        assert_eq!(meta.get("title").unwrap(), "Deploys: a retro");
+        assert_eq!(parse_list(meta.get("tags").unwrap()), vec!["infra", "rust"]);
+
+        let formatted = format_frontmatter(&meta);
+        assert!(formatted.contains("title: \"Deploys: a retro\"\n"));
+        assert!(formatted.contains("tags: [infra, rust]\n"));
+        assert_eq!(parse_frontmatter(&formatted).unwrap().0, meta);
+    }
+
+    #[test]
+    fn test_multiline_values() {
+        let mut meta = Meta::new();
+        for (key, value) in [
+            ("clip", "one\n\ntwo\n"),
+            ("keep", "kept\n\n"),
+            ("strip", "line one\nline two"),
+            ("indented", "  code\nmore"),
+        ] {
+            meta.insert(key.to_string(), value.to_string());
+        }
+        let formatted = format_frontmatter(&meta);
+        assert!(formatted.contains("strip: |-\n  line one\n  line two\n"));
+        assert_eq!(parse_frontmatter(&formatted).unwrap().0, meta);
+
+        let line = format_comment(&meta);
+        assert!(!line.contains('\n'));
+        assert_eq!(parse_comment(&line).unwrap(), meta);
+    }
+
+    #[test]
+    fn test_unclosed_frontmatter() {
+        assert!(parse_frontmatter("---\ntitle: x\n> Hi").is_none());
+    }
+
+    #[test]
+    fn test_comment_roundtrip() {
+        let mut meta = Meta::new();
+        meta.insert(
+            "note".to_string(),
+            "has \"quotes\" and --> arrows".to_string(),
+        );
+        meta.insert("empty".to_string(), String::new());
+        meta.insert("plain".to_string(), "value".to_string());
+
+        let line = format_comment(&meta);
+        assert!(!line[COMMENT_PREFIX.len()..line.len() - COMMENT_SUFFIX.len()].contains("-->"));
+        assert_eq!(parse_comment(&line).unwrap(), meta);
+    }
+}
@@ -0,0 +1,386 @@
+//! Interactive chat loop that edits a CMF file
+//!
+//! The file is re-read before every step, so edits made in another editor
+//! between messages are picked up, and every change is written back before
+//! the next prompt.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use cmf::backend::{Backend, Params};
+use cmf::branch;
+use cmf::file::{self, Expect};
+use cmf::{Document, Turn, UserMessage};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use crate::stream_reply;
+
+const HELP: &str = "\
+Enter sends a message; end a line with \\ to continue it, or wrap a block in \"\"\".
+/undo           remove the last turn
+/retry          ask for a new reply to the last message
+/fork [FILE]    continue in a new branch of the conversation
+/branches       list the branches of the conversation
+/switch FILE    continue in another branch
+/system [TEXT]  show or set the system prompt (`/system -` removes it)
+/save-as FILE   write the conversation to FILE and continue there
+/quit           leave (or Ctrl-D)";
+
+struct Repl<'a> {
+    path: PathBuf,
+    backend: &'a dyn Backend,
+    params: &'a Params,
+}
+
+/// Run the loop until the user quits
+pub fn run(path: &Path, backend: &dyn Backend, params: &Params) -> ExitCode {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let history = std::env::var_os("HOME").map(|home| Path::new(&home).join(".cmf_history"));
+    if let Some(ref history) = history {
+        let _ = editor.load_history(history);
+    }
+
+    let mut repl = Repl {
+        path: path.to_path_buf(),
+        backend,
+        params,
+    };
+    match repl.load() {
+        Ok((_, doc)) => println!(
+            "{}: {} turns (/help for commands)",
+            path.display(),
+            doc.turns.len()
+        ),
+        Err(e) => {
+            eprintln!("error: {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    loop {
+        let input = match read_input(&mut editor) {
+            Ok(Some(input)) => input,
+            Ok(None) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return ExitCode::FAILURE;
+            }
+        };
+        let _ = editor.add_history_entry(input.as_str());
+
+        let result = match input.strip_prefix('/') {
+            Some(command) => match repl.command(command) {
+                Ok(true) => continue,
+                Ok(false) => break,
+                Err(e) => Err(e),
+            },
+            None => repl.send(&input),
+        };
+        if let Err(e) = result {
+            eprintln!("error: {}: {}", repl.path.display(), e);
+        }
+    }
+
+    if let Some(ref history) = history {
+        let _ = editor.save_history(history);
+    }
+    ExitCode::SUCCESS
+}
+
+/// Read one message, joining continued lines; `None` for blank input
+fn read_input(editor: &mut DefaultEditor) -> Result<Option<String>, ReadlineError> {
+    let first = match editor.readline("you> ") {
+        Ok(line) => line,
+        // Ctrl-C discards the current input
+        Err(ReadlineError::Interrupted) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let mut lines = Vec::new();
+    if first.trim() == "\"\"\"" {
+        loop {
+            let line = editor.readline("...> ")?;
+            if line.trim() == "\"\"\"" {
+                break;
+            }
+            lines.push(line);
+        }
+    } else {
+        let mut line = first;
+        while let Some(continued) = line.strip_suffix('\\') {
+            lines.push(continued.to_string());
+            line = editor.readline("...> ")?;
+        }
+        lines.push(line);
+    }
+
+    let input = lines.join("\n");
+    Ok((!input.trim().is_empty()).then(|| input.trim_end().to_string()))
+}
+
+impl Repl<'_> {
+    /// Read the conversation from disk; a missing file is an empty conversation
+    fn load(&self) -> io::Result<(String, Document)> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let doc = Document::parse(&content);
+        Ok((content, doc))
+    }
+
+    /// Write an edited document back, failing if the file changed meanwhile
+    fn save(&self, content: &str, doc: &Document) -> Result<(), Box<dyn std::error::Error>> {
+        file::rewrite(&self.path, doc, Expect::Len(content.len() as u64))?;
+        Ok(())
+    }
+
+    fn send(&self, message: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let (content, _) = self.load()?;
+        let turn = Turn {
+            user: UserMessage {
+                content: message.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        file::append_turn(&self.path, &turn, Expect::Len(content.len() as u64))?;
+        self.reply()
+    }
+
+    fn reply(&self) -> Result<(), Box<dyn std::error::Error>> {
+        stream_reply(&self.path, self.backend, self.params)?;
+        Ok(())
+    }
+
+    /// Run a slash command; `false` ends the loop
+    fn command(&mut self, command: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+        let argument = argument.trim();
+
+        match name {
+            "help" => println!("{}", HELP),
+            "quit" | "exit" => return Ok(false),
+            "undo" => {
+                let (content, mut doc) = self.load()?;
+                let Some(last) = doc.turns.len().checked_sub(1) else {
+                    return Err("nothing to undo".into());
+                };
+                doc.remove_turn(last)?;
+                self.save(&content, &doc)?;
+                println!("removed turn {}", last + 1);
+            }
+            "retry" => {
+                let (content, mut doc) = self.load()?;
+                let Some(last) = doc.turns.len().checked_sub(1) else {
+                    return Err("no message to retry".into());
+                };
+                if !doc.turns[last].assistant.is_empty() || !doc.turns[last].meta.is_empty() {
+                    doc.replace_assistant(last, "")?;
+                    doc.turns[last].meta.clear();
+                    self.save(&content, &doc)?;
+                }
+                self.reply()?;
+            }
+            "fork" => {
+                let target = match argument {
+                    "" => branch::fork_path(&self.path),
+                    name => self.path.with_file_name(name),
+                };
+                let (_, doc) = self.load()?;
+                let fork = doc.fork(doc.turns.len(), &branch::parent_link(&self.path, &target))?;
+                self.switch_to(target, &fork)?;
+                println!("forked to {}", self.path.display());
+            }
+            "branches" => {
+                for branch in branch::branches(&self.path)? {
+                    let marker = if branch::same_file(&branch.path, &self.path) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    println!(
+                        "{} {}{} ({} turns)",
+                        marker,
+                        "  ".repeat(branch.depth),
+                        branch.path.display(),
+                        branch.turns
+                    );
+                }
+            }
+            "switch" => {
+                if argument.is_empty() {
+                    return Err("usage: /switch FILE".into());
+                }
+                self.path = branch::switch_path(&self.path, argument)?;
+                let (_, doc) = self.load()?;
+                println!(
+                    "now editing {}: {} turns",
+                    self.path.display(),
+                    doc.turns.len()
+                );
+            }
+            "save-as" => {
+                if argument.is_empty() {
+                    return Err("usage: /save-as FILE".into());
+                }
+                let (_, doc) = self.load()?;
+                self.switch_to(self.path.with_file_name(argument), &doc)?;
+                println!("now editing {}", self.path.display());
+            }
+            "system" => {
+                let (content, mut doc) = self.load()?;
+                match argument {
+                    "" => match doc.system() {
+                        Some(system) => println!("{}", system),
+                        None => println!("no system prompt"),
+                    },
+                    "-" => {
+                        doc.meta.remove("system");
+                        self.save(&content, &doc)?;
+                    }
+                    system => {
+                        doc.meta.insert("system".to_string(), system.to_string());
+                        self.save(&content, &doc)?;
+                    }
+                }
+            }
+            _ => return Err(format!("unknown command /{} (try /help)", name).into()),
+        }
+        Ok(true)
+    }
+
+    /// Write `doc` to a new file and continue there
+    fn switch_to(
+        &mut self,
+        target: PathBuf,
+        doc: &Document,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if target.exists() {
+            return Err(format!("{} already exists", target.display()).into());
+        }
+        file::rewrite(&target, doc, Expect::Len(0))?;
+        self.path = target;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmf::backend::Scripted;
+
+    /// A fresh directory holding `chat.cmf`, so forks land next to it
+    fn temp_chat(name: &str, content: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cmf-repl-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("chat.cmf");
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn read(path: &Path) -> Document {
+        Document::parse(&fs::read_to_string(path).unwrap())
+    }
+
+    #[test]
+    fn test_undo_and_retry() {
+        let path = temp_chat("retry", "> One\nA\n\n> Two\nB");
+        let backend = Scripted::text(["C"]);
+        let params = Params::default();
+        let mut repl = Repl {
+            path: path.clone(),
+            backend: &backend,
+            params: &params,
+        };
+
+        repl.command("undo").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "> One\nA\n");
+
+        repl.command("retry").unwrap();
+        let (request, _) = &backend.requests()[0];
+        assert_eq!(request.turns.len(), 1);
+        assert_eq!(request.turns[0].assistant, "");
+        assert_eq!(read(&path).turns[0].assistant, "C");
+
+        repl.command("undo").unwrap();
+        assert!(repl.command("undo").is_err());
+        assert!(repl.command("retry").is_err());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_system() {
+        let path = temp_chat("system", "# Notes\n\n> Hi\nHello");
+        let backend = Scripted::text([]);
+        let params = Params::default();
+        let mut repl = Repl {
+            path: path.clone(),
+            backend: &backend,
+            params: &params,
+        };
+
+        repl.command("system Be brief.\nUse examples.").unwrap();
+        let doc = read(&path);
+        assert_eq!(doc.system(), Some("Be brief.\nUse examples."));
+        assert_eq!(doc.turns[0].assistant, "Hello");
+        assert!(fs::read_to_string(&path).unwrap().contains("# Notes"));
+
+        repl.command("system -").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Notes\n\n> Hi\nHello\n"
+        );
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_fork_and_save_as() {
+        let path = temp_chat("fork", "> Hi\nHello");
+        let dir = path.parent().unwrap().to_path_buf();
+        let backend = Scripted::text([]);
+        let params = Params::default();
+        let mut repl = Repl {
+            path: path.clone(),
+            backend: &backend,
+            params: &params,
+        };
+
+        // Names are taken relative to the current file, like /switch
+        repl.command("fork other.cmf").unwrap();
+        assert_eq!(repl.path, dir.join("other.cmf"));
+        let fork = read(&repl.path);
+        assert_eq!(fork.turns.len(), 1);
+        assert_eq!(
+            fork.meta.get("parent").map(String::as_str),
+            Some("chat.cmf")
+        );
+        assert!(repl.command("fork other.cmf").is_err());
+
+        repl.command("save-as copy.cmf").unwrap();
+        assert_eq!(repl.path, dir.join("copy.cmf"));
+        assert_eq!(read(&repl.path).turns, fork.turns);
+
+        repl.command("switch chat.cmf").unwrap();
+        let expected = branch::fork_path(&path);
+        repl.command("fork").unwrap();
+        assert_eq!(repl.path, expected);
+        assert_eq!(read(&path).turns.len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,545 @@
+//! Assistant replies from raw API responses
+//!
+//! Reads the reply out of OpenAI Chat Completions, OpenAI Responses,
+//! Anthropic Messages and Ollama chat responses, either as a single JSON
+//! object or as a stream of chunks (`text/event-stream`, or newline-delimited
+//! JSON for Ollama). Tool calls are kept alongside the text and
+//! written into the reply as fenced `tool-call` blocks:
+//!
+//! ````markdown
+//! ```tool-call get_weather id=call_1
+//! {
+//!   "city": "Paris"
+//! }
+//! ```
+//! ````
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+/// A tool call requested by the assistant
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolCall {
+    pub id: Option<String>,
+    pub name: String,
+    /// Arguments as a JSON string
+    pub arguments: String,
+}
+
+/// The assistant reply carried by a response
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reply {
+    pub text: String,
+    pub tool_calls: Vec<ToolCall>,
+    /// Why generation stopped, as reported by the API (`stop`, `length`, `end_turn`, ...)
+    pub stop_reason: Option<String>,
+}
+
+/// An error raised while reading a response
+#[derive(Debug)]
+pub enum ResponseError {
+    Json(serde_json::Error),
+    /// The API returned an error object instead of a reply
+    Api(String),
+    Format(String),
+}
+
+impl std::fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResponseError::Json(e) => write!(f, "invalid JSON: {}", e),
+            ResponseError::Api(message) => write!(f, "API error: {}", message),
+            ResponseError::Format(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ResponseError {}
+
+impl From<serde_json::Error> for ResponseError {
+    fn from(e: serde_json::Error) -> Self {
+        ResponseError::Json(e)
+    }
+}
+
+impl Reply {
+    /// Whether the reply has neither text nor tool calls
+    pub fn is_empty(&self) -> bool {
+        self.text.trim().is_empty() && self.tool_calls.is_empty()
+    }
+
+    /// Whether generation stopped at the token limit
+    pub fn is_truncated(&self) -> bool {
+        matches!(
+            self.stop_reason.as_deref(),
+            Some("length" | "max_tokens" | "max_output_tokens")
+        )
+    }
+
+    /// The reply as assistant content, with tool calls as fenced blocks
+    pub fn to_markdown(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.text.trim().is_empty() {
+            parts.push(self.text.trim_end().to_string());
+        }
+        for call in &self.tool_calls {
+            let mut info = format!("tool-call {}", call.name);
+            if let Some(ref id) = call.id {
+                info.push_str(&format!(" id={}", id));
+            }
+            // Pretty-print arguments so the block reads well and diffs cleanly
+            let arguments = serde_json::from_str::<Value>(&call.arguments)
+                .and_then(|value| serde_json::to_string_pretty(&value))
+                .unwrap_or_else(|_| call.arguments.clone());
+            parts.push(format!("```{}\n{}\n```", info, arguments));
+        }
+        parts.join("\n\n")
+    }
+}
+
+/// Parse a complete response object or event stream
+pub fn parse(input: &str) -> Result<Reply, ResponseError> {
+    let trimmed = input.trim_start_matches('\u{feff}').trim_start();
+    let mut builder = ReplyBuilder::default();
+    if trimmed.starts_with('{') {
+        return match serde_json::from_str(trimmed) {
+            Ok(value) => parse_object(&value),
+            // Newline-delimited chunks
+            Err(_) if trimmed.lines().filter(|l| !l.trim().is_empty()).count() > 1 => {
+                for line in trimmed.lines() {
+                    builder.push_event(line)?;
+                }
+                Ok(builder.finish())
+            }
+            Err(e) => Err(e.into()),
+        };
+    }
+
+    let mut events = 0;
+    for data in sse_data(trimmed) {
+        builder.push_event(&data)?;
+        events += 1;
+    }
+    if events == 0 {
+        return Err(ResponseError::Format(
+            "expected a JSON response or an event stream".to_string(),
+        ));
+    }
+    Ok(builder.finish())
+}
+
+/// Parse a non-streaming response object
+pub fn parse_object(value: &Value) -> Result<Reply, ResponseError> {
+    check_error(value)?;
+
+    // Chat Completions
+    if let Some(choices) = value.get("choices").and_then(Value::as_array) {
+        let choice = choices
+            .first()
+            .ok_or_else(|| ResponseError::Format("response has no choices".to_string()))?;
+        let message = &choice["message"];
+        let mut reply = Reply {
+            text: content_text(&message["content"]),
+            stop_reason: string(&choice["finish_reason"]),
+            ..Default::default()
+        };
+        for call in message["tool_calls"].as_array().into_iter().flatten() {
+            reply.tool_calls.push(ToolCall {
+                id: string(&call["id"]),
+                name: string(&call["function"]["name"]).unwrap_or_default(),
+                arguments: string(&call["function"]["arguments"]).unwrap_or_default(),
+            });
+        }
+        return Ok(reply);
+    }
+
+    // Responses
+    if let Some(output) = value.get("output").and_then(Value::as_array) {
+        let mut reply = Reply {
+            stop_reason: string(&value["incomplete_details"]["reason"]),
+            ..Default::default()
+        };
+        for item in output {
+            match item["type"].as_str() {
+                Some("message") => reply.text.push_str(&content_text(&item["content"])),
+                Some("function_call") => reply.tool_calls.push(ToolCall {
+                    id: string(&item["call_id"]),
+                    name: string(&item["name"]).unwrap_or_default(),
+                    arguments: string(&item["arguments"]).unwrap_or_default(),
+                }),
+                // Reasoning summaries, web search calls and so on
+                _ => {}
+            }
+        }
+        return Ok(reply);
+    }
+
+    // Ollama
+    if is_ollama(value) {
+        let message = &value["message"];
+        return Ok(Reply {
+            text: content_text(&message["content"]),
+            tool_calls: message["tool_calls"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(ollama_tool_call)
+                .collect(),
+            stop_reason: string(&value["done_reason"]),
+        });
+    }
+
+    // Anthropic Messages
+    if value["type"] == "message" {
+        let mut reply = Reply {
+            stop_reason: string(&value["stop_reason"]),
+            ..Default::default()
+        };
+        for block in value["content"].as_array().into_iter().flatten() {
+            match block["type"].as_str() {
+                Some("text") => reply.text.push_str(block["text"].as_str().unwrap_or("")),
+                Some("tool_use") => reply.tool_calls.push(ToolCall {
+                    id: string(&block["id"]),
+                    name: string(&block["name"]).unwrap_or_default(),
+                    arguments: block["input"].to_string(),
+                }),
+                _ => {}
+            }
+        }
+        return Ok(reply);
+    }
+
+    Err(ResponseError::Format(
+        "not a Chat Completions, Responses, Anthropic or Ollama response".to_string(),
+    ))
+}
+
+/// The `data:` payloads of a `text/event-stream`, one per event
+pub fn sse_data(input: &str) -> impl Iterator<Item = String> + '_ {
+    input
+        .split("\n\n")
+        .flat_map(|block| block.split("\r\n\r\n"))
+        .filter_map(|block| {
+            let lines: Vec<&str> = block
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                .collect();
+            (!lines.is_empty()).then(|| lines.join("\n"))
+        })
+}
+
+/// Rebuilds a reply from streamed chunks
+///
+/// Each event adds to the reply in place; `push_event` returns the text the
+/// event added so callers can display the reply as it arrives.
+#[derive(Debug, Default)]
+pub struct ReplyBuilder {
+    reply: Reply,
+    /// Tool calls by stream index; arguments arrive in pieces
+    calls: BTreeMap<u64, ToolCall>,
+    /// A final response object that supersedes the chunks seen so far
+    completed: Option<Reply>,
+}
+
+impl ReplyBuilder {
+    /// Add the JSON payload of one event
+    pub fn push_event(&mut self, data: &str) -> Result<String, ResponseError> {
+        let data = data.trim();
+        if data.is_empty() || data == "[DONE]" {
+            return Ok(String::new());
+        }
+        let event: Value = serde_json::from_str(data)?;
+        check_error(&event)?;
+
+        let mut added = String::new();
+
+        // Chat Completions chunks
+        if let Some(choices) = event.get("choices").and_then(Value::as_array) {
+            // Only the first choice is kept; `n > 1` streams interleave them
+            let Some(choice) = choices
+                .iter()
+                .find(|c| c["index"].as_u64().unwrap_or(0) == 0)
+            else {
+                return Ok(added);
+            };
+            let delta = &choice["delta"];
+            added.push_str(&content_text(&delta["content"]));
+            for call in delta["tool_calls"].as_array().into_iter().flatten() {
+                let entry = self
+                    .calls
+                    .entry(call["index"].as_u64().unwrap_or(0))
+                    .or_default();
+                if let Some(id) = string(&call["id"]) {
+                    entry.id = Some(id);
+                }
+                if let Some(name) = call["function"]["name"].as_str() {
+                    entry.name.push_str(name);
+                }
+                if let Some(arguments) = call["function"]["arguments"].as_str() {
+                    entry.arguments.push_str(arguments);
+                }
+            }
+            if let Some(reason) = string(&choice["finish_reason"]) {
+                self.reply.stop_reason = Some(reason);
+            }
+            self.reply.text.push_str(&added);
+            return Ok(added);
+        }
+
+        // Ollama chunks carry whole tool calls
+        if is_ollama(&event) {
+            let message = &event["message"];
+            added.push_str(&content_text(&message["content"]));
+            for call in message["tool_calls"].as_array().into_iter().flatten() {
+                let index = self.calls.len() as u64;
+                self.calls.insert(index, ollama_tool_call(call));
+            }
+            if let Some(reason) = string(&event["done_reason"]) {
+                self.reply.stop_reason = Some(reason);
+            }
+            self.reply.text.push_str(&added);
+            return Ok(added);
+        }
+
+        let index = event["output_index"]
+            .as_u64()
+            .or_else(|| event["index"].as_u64())
+            .unwrap_or(0);
+        match event["type"].as_str().unwrap_or("") {
+            // Responses events
+            "response.output_text.delta" => {
+                added.push_str(event["delta"].as_str().unwrap_or(""));
+            }
+            "response.output_item.added" | "response.output_item.done"
+                if event["item"]["type"] == "function_call" =>
+            {
+                let item = &event["item"];
+                let done = event["type"] == "response.output_item.done";
+                let entry = self.calls.entry(index).or_default();
+                entry.id = string(&item["call_id"]);
+                entry.name = string(&item["name"]).unwrap_or_default();
+                if done || entry.arguments.is_empty() {
+                    entry.arguments = string(&item["arguments"]).unwrap_or_default();
+                }
+            }
+            "response.function_call_arguments.delta" => {
+                let entry = self.calls.entry(index).or_default();
+                entry
+                    .arguments
+                    .push_str(event["delta"].as_str().unwrap_or(""));
+            }
+            "response.completed" | "response.incomplete" => {
+                self.completed = Some(parse_object(&event["response"])?);
+            }
+            "response.failed" => {
+                let message = string(&event["response"]["error"]["message"])
+                    .unwrap_or_else(|| "response failed".to_string());
+                return Err(ResponseError::Api(message));
+            }
+
+            // Anthropic events
+            "content_block_start" if event["content_block"]["type"] == "tool_use" => {
+                let block = &event["content_block"];
+                self.calls.insert(
+                    index,
+                    ToolCall {
+                        id: string(&block["id"]),
+                        name: string(&block["name"]).unwrap_or_default(),
+                        arguments: String::new(),
+                    },
+                );
+            }
+            "content_block_start" => {
+                added.push_str(event["content_block"]["text"].as_str().unwrap_or(""));
+            }
+            "content_block_delta" => {
+                let delta = &event["delta"];
+                match delta["type"].as_str() {
+                    Some("text_delta") => added.push_str(delta["text"].as_str().unwrap_or("")),
+                    Some("input_json_delta") => {
+                        let entry = self.calls.entry(index).or_default();
+                        entry
+                            .arguments
+                            .push_str(delta["partial_json"].as_str().unwrap_or(""));
+                    }
+                    _ => {}
+                }
+            }
+            "message_delta" => {
+                if let Some(reason) = string(&event["delta"]["stop_reason"]) {
+                    self.reply.stop_reason = Some(reason);
+                }
+            }
+
+            _ => {}
+        }
+
+        self.reply.text.push_str(&added);
+        Ok(added)
+    }
+
+    /// The reply rebuilt so far
+    pub fn finish(mut self) -> Reply {
+        if let Some(completed) = self.completed {
+            return completed;
+        }
+        self.reply.tool_calls = self
+            .calls
+            .into_values()
+            .map(|mut call| {
+                // A tool called with no arguments streams none
+                if call.arguments.is_empty() {
+                    call.arguments = "{}".to_string();
+                }
+                call
+            })
+            .collect();
+        self.reply
+    }
+}
+
+fn check_error(value: &Value) -> Result<(), ResponseError> {
+    let error = &value["error"];
+    if error.is_null() {
+        return Ok(());
+    }
+    let message = error["message"]
+        .as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| error.to_string());
+    Err(ResponseError::Api(message))
+}
+
+fn is_ollama(value: &Value) -> bool {
+    value["message"].is_object() && value.get("done").is_some()
+}
+
+fn ollama_tool_call(call: &Value) -> ToolCall {
+    ToolCall {
+        id: string(&call["id"]),
+        name: string(&call["function"]["name"]).unwrap_or_default(),
+        arguments: call["function"]["arguments"].to_string(),
+    }
+}
+
+/// Text of a `content` field: a string or an array of text parts
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::String(s) => s.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter(|part| matches!(part["type"].as_str(), Some("text" | "output_text")))
+            .filter_map(|part| part["text"].as_str())
+            .collect(),
+        _ => String::new(),
+    }
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_completion_with_tool_call() {
+        let input = r#"{"object": "chat.completion", "choices": [{"index": 0, "finish_reason": "tool_calls",
+            "message": {"role": "assistant", "content": "Checking.", "tool_calls": [{"id": "call_1",
+            "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}}]}}]}"#;
+
+        let reply = parse(input).unwrap();
+        assert_eq!(reply.text, "Checking.");
+        assert_eq!(reply.tool_calls[0].name, "get_weather");
+        assert_eq!(
+            reply.to_markdown(),
+            "Checking.\n\n```tool-call get_weather id=call_1\n{\n  \"city\": \"Paris\"\n}\n```"
+        );
+    }
+
+    #[test]
+    fn test_responses_and_anthropic_objects() {
+        let responses = r#"{"object": "response", "output": [
+            {"type": "reasoning", "summary": []},
+            {"type": "message", "content": [{"type": "output_text", "text": "Hello"}]}]}"#;
+        assert_eq!(parse(responses).unwrap().text, "Hello");
+
+        let anthropic = r#"{"type": "message", "role": "assistant", "stop_reason": "max_tokens",
+            "content": [{"type": "text", "text": "Hi"}, {"type": "tool_use", "id": "toolu_1",
+            "name": "search", "input": {"q": "cmf"}}]}"#;
+        let reply = parse(anthropic).unwrap();
+        assert_eq!(reply.text, "Hi");
+        assert_eq!(reply.tool_calls[0].arguments, r#"{"q":"cmf"}"#);
+        assert!(reply.is_truncated());
+    }
+
+    #[test]
+    fn test_chat_completion_stream() {
+        let input = "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\"}}]}\n\n\
+                     data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hel\"}}]}\n\n\
+                     data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"}}]}\n\n\
+                     data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"function\":{\"name\":\"f\",\"arguments\":\"{\\\"a\\\"\"}}]}}]}\n\n\
+                     data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\":1}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n\
+                     data: [DONE]\n\n";
+
+        let reply = parse(input).unwrap();
+        assert_eq!(reply.text, "Hello");
+        assert_eq!(reply.tool_calls[0].arguments, "{\"a\":1}");
+        assert_eq!(reply.stop_reason.as_deref(), Some("tool_calls"));
+    }
+
+    #[test]
+    fn test_anthropic_stream() {
+        let input = "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"content\":[]}}\n\n\
+                     event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n\
+                     event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Sure\"}}\n\n\
+                     event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"ls\",\"input\":{}}}\n\n\
+                     event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"}}\n\n\
+                     event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n";
+
+        let reply = parse(input).unwrap();
+        assert_eq!(reply.text, "Sure");
+        assert_eq!(reply.tool_calls[0].arguments, "{}");
+    }
+
+    #[test]
+    fn test_responses_stream_prefers_completed() {
+        let mut builder = ReplyBuilder::default();
+        let added = builder
+            .push_event(r#"{"type":"response.output_text.delta","output_index":0,"delta":"Hi"}"#)
+            .unwrap();
+        assert_eq!(added, "Hi");
+        builder
+            .push_event(r#"{"type":"response.completed","response":{"output":[{"type":"message","content":[{"type":"output_text","text":"Hi there"}]}]}}"#)
+            .unwrap();
+        assert_eq!(builder.finish().text, "Hi there");
+    }
+
+    #[test]
+    fn test_ollama_chunks() {
+        let mut builder = ReplyBuilder::default();
+        builder
+            .push_event(
+                r#"{"model":"llama3","message":{"role":"assistant","content":"Hi"},"done":false}"#,
+            )
+            .unwrap();
+        builder
+            .push_event(r#"{"model":"llama3","message":{"role":"assistant","content":"","tool_calls":[{"function":{"name":"ls","arguments":{"dir":"."}}}]},"done":true,"done_reason":"stop"}"#)
+            .unwrap();
+        let reply = builder.finish();
+        assert_eq!(reply.text, "Hi");
+        assert_eq!(reply.tool_calls[0].arguments, r#"{"dir":"."}"#);
+        assert_eq!(reply.stop_reason.as_deref(), Some("stop"));
+    }
+
+    #[test]
+    fn test_api_error() {
+        assert!(matches!(
+            parse(r#"{"error": {"message": "Invalid API key", "type": "invalid_request_error"}}"#),
+            Err(ResponseError::Api(message)) if message == "Invalid API key"
+        ));
+    }
+}
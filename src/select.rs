@@ -0,0 +1,323 @@
+//! Selecting turns with a small query language
+//!
+//! A selector is a list of terms applied left to right, each narrowing the
+//! turns selected so far:
+//!
+//! - `3`, `-1`, `2..4`, `-3..`, `first`, `last`: turns by position among
+//!   those still selected, counting from 1 (negative counts from the end;
+//!   ranges are inclusive)
+//! - `tag:infra`: the conversation has the tag
+//! - `user:alice`: alice asked
+//! - `question:REGEX`, `reply:REGEX`, `text:REGEX`: the user message, the
+//!   reply, or either matches
+//! - `code`, `code:rust`: the reply has a fenced code block (in that
+//!   language)
+//! - `[score>=4]`: an annotation [`Filter`]
+//!
+//! Any term but a position can be negated with `!`, and values with spaces
+//! can be quoted: `reply:"rate limit"`. So `user:alice code:rust` selects
+//! the turns where alice asked and got Rust code back, and
+//! `tag:infra last` the last turn of conversations tagged `infra`.
+
+use std::str::FromStr;
+
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag};
+use regex::Regex;
+
+use crate::annotation::Filter;
+use crate::{Document, Turn};
+
+/// A parsed selector expression
+#[derive(Debug, Clone)]
+pub struct Selector {
+    terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    /// A turn, counting from 1, or from the end if negative
+    Index(i64),
+    Range(Option<i64>, Option<i64>),
+    Predicate {
+        negated: bool,
+        predicate: Predicate,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Predicate {
+    Tag(String),
+    User(String),
+    Question(Regex),
+    Reply(Regex),
+    Text(Regex),
+    Code(Option<String>),
+    Annotation(Filter),
+}
+
+/// Why a selector could not be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectorError(pub String);
+
+impl std::fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid selector: {}", self.0)
+    }
+}
+
+impl std::error::Error for SelectorError {}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let terms = tokenize(expr)?
+            .iter()
+            .map(|token| parse_term(token))
+            .collect::<Result<_, _>>()?;
+        Ok(Selector { terms })
+    }
+}
+
+impl Selector {
+    /// Indices of the selected turns, in order
+    pub fn select(&self, doc: &Document) -> Vec<usize> {
+        let mut selected: Vec<usize> = (0..doc.turns.len()).collect();
+        for term in &self.terms {
+            selected = match term {
+                Term::Index(index) => position(*index, selected.len())
+                    .map(|i| vec![selected[i]])
+                    .unwrap_or_default(),
+                Term::Range(start, end) => {
+                    let len = selected.len() as i64;
+                    let start = start.map_or(0, |start| offset(start, len)).clamp(0, len);
+                    let end = end.map_or(len, |end| offset(end, len) + 1).clamp(0, len);
+                    selected[start as usize..end.max(start) as usize].to_vec()
+                }
+                Term::Predicate { negated, predicate } => selected
+                    .into_iter()
+                    .filter(|&i| predicate.matches(doc, &doc.turns[i]) != *negated)
+                    .collect(),
+            };
+        }
+        selected
+    }
+}
+
+impl Document {
+    /// Indices of the turns a selector expression picks, such as
+    /// `user:alice code:rust` or `2..4`
+    pub fn select(&self, expr: &str) -> Result<Vec<usize>, SelectorError> {
+        Ok(expr.parse::<Selector>()?.select(self))
+    }
+}
+
+impl Turn {
+    /// The language of each fenced code block in the reply, `""` if none
+    /// is given
+    pub fn code_blocks(&self) -> Vec<String> {
+        Parser::new(&self.assistant)
+            .filter_map(|event| match event {
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => Some(
+                    info.split_whitespace()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                ),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Predicate {
+    fn matches(&self, doc: &Document, turn: &Turn) -> bool {
+        match self {
+            Predicate::Tag(tag) => doc.tags().contains(tag),
+            Predicate::User(user) => turn.user.username.as_deref() == Some(user.as_str()),
+            Predicate::Question(regex) => regex.is_match(&turn.user.content),
+            Predicate::Reply(regex) => regex.is_match(&turn.assistant),
+            Predicate::Text(regex) => {
+                regex.is_match(&turn.user.content) || regex.is_match(&turn.assistant)
+            }
+            Predicate::Code(language) => {
+                let blocks = turn.code_blocks();
+                match language {
+                    Some(language) => blocks.iter().any(|block| block == language),
+                    None => !blocks.is_empty(),
+                }
+            }
+            Predicate::Annotation(filter) => filter.matches(turn),
+        }
+    }
+}
+
+/// Index into `len` items of a 1-based or from-the-end position
+fn position(index: i64, len: usize) -> Option<usize> {
+    let i = offset(index, len as i64);
+    (0..len as i64).contains(&i).then_some(i as usize)
+}
+
+/// A 1-based or from-the-end position as a 0-based index, which may fall
+/// outside `0..len`
+fn offset(index: i64, len: i64) -> i64 {
+    if index < 0 {
+        len + index
+    } else {
+        index - 1
+    }
+}
+
+/// Split at whitespace outside quotes and brackets
+fn tokenize(expr: &str) -> Result<Vec<String>, SelectorError> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut bracketed = false;
+    for c in expr.chars() {
+        match c {
+            '"' if !bracketed => quoted = !quoted,
+            '[' if !quoted => {
+                bracketed = true;
+                token.push(c);
+            }
+            ']' if !quoted => {
+                bracketed = false;
+                token.push(c);
+            }
+            c if c.is_whitespace() && !quoted && !bracketed => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if quoted || bracketed {
+        return Err(SelectorError(format!(
+            "{:?}: unclosed quote or bracket",
+            expr
+        )));
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn parse_term(token: &str) -> Result<Term, SelectorError> {
+    let error = |why: &str| SelectorError(format!("{:?}: {}", token, why));
+    let bound = |text: &str| -> Result<Option<i64>, SelectorError> {
+        match text {
+            "" => Ok(None),
+            _ => match text.parse::<i64>() {
+                Ok(0) | Err(_) => Err(error("expected a turn number")),
+                Ok(n) => Ok(Some(n)),
+            },
+        }
+    };
+
+    match token {
+        "first" => return Ok(Term::Index(1)),
+        "last" => return Ok(Term::Index(-1)),
+        _ => {}
+    }
+    if token
+        .chars()
+        .all(|c| c.is_ascii_digit() || c == '-' || c == '.')
+    {
+        return match token.split_once("..") {
+            Some((start, end)) => Ok(Term::Range(bound(start)?, bound(end)?)),
+            None => Ok(Term::Index(bound(token)?.unwrap())),
+        };
+    }
+
+    let (negated, token) = match token.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, token),
+    };
+    if let Some(filter) = token.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        let filter = filter
+            .parse()
+            .map_err(|e: crate::annotation::FilterError| error(&e.0))?;
+        return Ok(Term::Predicate {
+            negated,
+            predicate: Predicate::Annotation(filter),
+        });
+    }
+
+    let (key, value) = token.split_once(':').unwrap_or((token, ""));
+    let regex = || Regex::new(value).map_err(|e| error(&e.to_string()));
+    let value_required = || {
+        if value.is_empty() {
+            Err(error("expected a value after `:`"))
+        } else {
+            Ok(value.to_string())
+        }
+    };
+    let predicate = match key {
+        "tag" => Predicate::Tag(value_required()?),
+        "user" => Predicate::User(value_required()?),
+        "question" => Predicate::Question(regex()?),
+        "reply" => Predicate::Reply(regex()?),
+        "text" => Predicate::Text(regex()?),
+        "code" => Predicate::Code((!value.is_empty()).then(|| value.to_string())),
+        _ => return Err(error("unknown term")),
+    };
+    Ok(Term::Predicate { negated, predicate })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAT: &str = "---\ntags: [infra, rust]\n---\n\n> @alice: How do I read a file?\n```rust\nlet text = fs::read_to_string(path)?;\n```\n\n> @bob: And in Python?\n<!-- cmf: score=2 -->\n```python\nopen(path).read()\n```\n\n> @alice: Thanks!\nYou're welcome.\n\n> @alice: Any rate limit?\n<!-- cmf: score=5 -->\nNo rate limit applies.";
+
+    fn selected(expr: &str) -> Vec<usize> {
+        Document::parse(CHAT).select(expr).unwrap()
+    }
+
+    #[test]
+    fn test_positions() {
+        assert_eq!(selected("2"), vec![1]);
+        assert_eq!(selected("last"), vec![3]);
+        assert_eq!(selected("-2"), vec![2]);
+        assert_eq!(selected("2..3"), vec![1, 2]);
+        assert_eq!(selected("-2.."), vec![2, 3]);
+        assert_eq!(selected("..1"), vec![0]);
+        assert_eq!(selected("3..9"), vec![2, 3]);
+        assert_eq!(selected("9"), Vec::<usize>::new());
+        assert_eq!(selected(""), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_predicates() {
+        assert_eq!(selected("user:alice code:rust"), vec![0]);
+        assert_eq!(selected("code"), vec![0, 1]);
+        assert_eq!(selected("!code"), vec![2, 3]);
+        assert_eq!(selected("tag:infra last"), vec![3]);
+        assert_eq!(selected("tag:web"), Vec::<usize>::new());
+        assert_eq!(selected(r#"reply:"rate limit""#), vec![3]);
+        assert_eq!(selected("text:(?i)python"), vec![1]);
+        assert_eq!(selected("reply:fs..read"), vec![0]);
+        assert_eq!(selected("[score>=2] 1"), vec![1]);
+        // Positions count among the turns selected so far
+        assert_eq!(selected("user:alice 2"), vec![2]);
+        assert_eq!(selected("2 user:alice"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!("0".parse::<Selector>().is_err());
+        assert!("colour:red".parse::<Selector>().is_err());
+        assert!("user:".parse::<Selector>().is_err());
+        assert!("reply:(".parse::<Selector>().is_err());
+        assert!("[score>=".parse::<Selector>().is_err());
+    }
+
+    #[test]
+    fn test_code_blocks() {
+        let doc = Document::parse("> q\n```rust\nfn main() {}\n```\n\n```\nplain\n```");
+        assert_eq!(doc.turns[0].code_blocks(), vec!["rust", ""]);
+    }
+}
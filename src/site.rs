@@ -0,0 +1,390 @@
+//! Static site generator for a directory of conversations
+//!
+//! Builds an archive that works straight from the filesystem:
+//! - `index.html` listing every conversation, newest first, with search
+//! - one page per conversation, rendered like `cmf to-html`
+//! - `tags/<tag>.html` for every frontmatter tag
+//! - `search-index.json`, an inverted index (term -> `[doc, count]` pairs),
+//!   and `search-index.js`, the same index loadable over `file://`
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::html::{escape, page, render_turns};
+use crate::Document;
+
+/// Summary of a finished build
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiteReport {
+    pub conversations: usize,
+    pub tags: usize,
+}
+
+struct Entry {
+    slug: String,
+    title: String,
+    date: Option<String>,
+    tags: Vec<String>,
+    doc: Document,
+}
+
+#[derive(Serialize)]
+struct SearchIndex<'a> {
+    docs: Vec<SearchDoc<'a>>,
+    terms: BTreeMap<String, Vec<(usize, usize)>>,
+}
+
+#[derive(Serialize)]
+struct SearchDoc<'a> {
+    title: &'a str,
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<&'a str>,
+}
+
+const SEARCH_SCRIPT: &str = r#"
+(function () {
+  var index = window.cmfSearchIndex;
+  var input = document.getElementById("search");
+  var results = document.getElementById("results");
+  var all = document.getElementById("conversations");
+  input.addEventListener("input", function () {
+    var terms = input.value.toLowerCase().match(/[\p{L}\p{N}]+/gu) || [];
+    terms = terms.filter(function (t) { return t.length > 1; });
+    results.innerHTML = "";
+    if (!terms.length) { results.hidden = true; all.hidden = false; return; }
+    var scores = null;
+    terms.forEach(function (term) {
+      var found = {};
+      Object.keys(index.terms).forEach(function (key) {
+        if (key.indexOf(term) !== 0) return;
+        index.terms[key].forEach(function (posting) {
+          found[posting[0]] = (found[posting[0]] || 0) + posting[1];
+        });
+      });
+      if (scores === null) { scores = found; return; }
+      Object.keys(scores).forEach(function (doc) {
+        if (found[doc] === undefined) delete scores[doc]; else scores[doc] += found[doc];
+      });
+    });
+    Object.keys(scores).sort(function (a, b) { return scores[b] - scores[a]; }).forEach(function (doc) {
+      var d = index.docs[doc];
+      var li = document.createElement("li");
+      var a = document.createElement("a");
+      a.href = d.url;
+      a.textContent = d.title;
+      li.appendChild(a);
+      results.appendChild(li);
+    });
+    if (!results.children.length) {
+      var empty = document.createElement("li");
+      empty.textContent = "No matches";
+      results.appendChild(empty);
+    }
+    results.hidden = false;
+    all.hidden = true;
+  });
+})();
+"#;
+
+/// Page names the archive itself uses
+const RESERVED_SLUGS: [&str; 2] = ["index", "tags"];
+
+/// Build a site from every `.cmf` file under `input` into `output`
+pub fn build(input: &Path, output: &Path) -> io::Result<SiteReport> {
+    let mut files = Vec::new();
+    collect_files(input, &mut files)?;
+    files.sort();
+
+    let mut used_slugs: HashSet<String> = RESERVED_SLUGS.iter().map(|s| s.to_string()).collect();
+    let mut entries = Vec::new();
+    for path in files {
+        let content = fs::read_to_string(&path)?;
+        let doc = Document::parse(&content);
+        let relative = path.strip_prefix(input).unwrap_or(&path).with_extension("");
+        let slug = unique_slug(&slugify(&relative.to_string_lossy()), &mut used_slugs);
+        let title = doc
+            .title()
+            .map(str::to_string)
+            .unwrap_or_else(|| relative.to_string_lossy().into_owned());
+        entries.push(Entry {
+            slug,
+            title,
+            date: doc.date().map(str::to_string),
+            tags: doc.tags(),
+            doc,
+        });
+    }
+
+    // Newest first; undated conversations go last, alphabetically
+    entries.sort_by(|a, b| match (&a.date, &b.date) {
+        (Some(x), Some(y)) => y.cmp(x).then_with(|| a.title.cmp(&b.title)),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.title.cmp(&b.title),
+    });
+
+    let mut tags: BTreeMap<&str, Vec<&Entry>> = BTreeMap::new();
+    for entry in &entries {
+        for tag in &entry.tags {
+            tags.entry(tag.as_str()).or_default().push(entry);
+        }
+    }
+    let mut used_tag_slugs = HashSet::new();
+    let tag_slugs: BTreeMap<&str, String> = tags
+        .keys()
+        .map(|tag| (*tag, unique_slug(&slugify(tag), &mut used_tag_slugs)))
+        .collect();
+
+    fs::create_dir_all(output.join("tags"))?;
+
+    for entry in &entries {
+        let mut body =
+            String::from("<nav><a href=\"index.html\">&larr; All conversations</a></nav>\n");
+        body.push_str(&entry_meta(entry, &tag_slugs, ""));
+        body.push_str(&render_turns(&entry.doc));
+        fs::write(
+            output.join(format!("{}.html", entry.slug)),
+            page(&entry.title, &body),
+        )?;
+    }
+
+    for (tag, tagged) in &tags {
+        let mut body =
+            String::from("<nav><a href=\"../index.html\">&larr; All conversations</a></nav>\n");
+        body.push_str(&entry_list(tagged, &tag_slugs, "../", "conversations"));
+        fs::write(
+            output.join("tags").join(format!("{}.html", tag_slugs[tag])),
+            page(&format!("Tagged \u{201c}{}\u{201d}", tag), &body),
+        )?;
+    }
+
+    let index = search_index(&entries);
+    let json = serde_json::to_string(&index).map_err(io::Error::other)?;
+    fs::write(output.join("search-index.json"), &json)?;
+    fs::write(
+        output.join("search-index.js"),
+        format!("window.cmfSearchIndex = {};\n", json),
+    )?;
+
+    let all: Vec<&Entry> = entries.iter().collect();
+    let mut body = String::from(
+        "<input class=\"search\" id=\"search\" type=\"search\" placeholder=\"Search conversations\" aria-label=\"Search conversations\">\n\
+         <ul class=\"conversations\" id=\"results\" hidden></ul>\n",
+    );
+    body.push_str(&entry_list(&all, &tag_slugs, "", "conversations"));
+    if !tags.is_empty() {
+        body.push_str("<h2>Tags</h2>\n<p class=\"tags\">");
+        for (tag, tagged) in &tags {
+            body.push_str(&format!(
+                "<a class=\"tag\" href=\"tags/{}.html\">{}</a> <span class=\"meta\">{}</span> ",
+                tag_slugs[tag],
+                escape(tag),
+                tagged.len()
+            ));
+        }
+        body.push_str("</p>\n");
+    }
+    body.push_str("<script src=\"search-index.js\"></script>\n");
+    body.push_str(&format!("<script>{}</script>\n", SEARCH_SCRIPT));
+    fs::write(output.join("index.html"), page("Conversations", &body))?;
+
+    Ok(SiteReport {
+        conversations: entries.len(),
+        tags: tags.len(),
+    })
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "cmf") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn entry_list(
+    entries: &[&Entry],
+    tag_slugs: &BTreeMap<&str, String>,
+    root: &str,
+    id: &str,
+) -> String {
+    let mut output = format!("<ul class=\"conversations\" id=\"{}\">\n", id);
+    for entry in entries {
+        output.push_str(&format!(
+            "<li><a href=\"{}{}.html\">{}</a>{}</li>\n",
+            root,
+            entry.slug,
+            escape(&entry.title),
+            entry_meta(entry, tag_slugs, root)
+        ));
+    }
+    output.push_str("</ul>\n");
+    output
+}
+
+fn entry_meta(entry: &Entry, tag_slugs: &BTreeMap<&str, String>, root: &str) -> String {
+    let mut output = String::from("<p class=\"meta\">");
+    if let Some(ref date) = entry.date {
+        output.push_str(&format!("<time>{}</time> ", escape(date)));
+    }
+    for tag in &entry.tags {
+        output.push_str(&format!(
+            "<a class=\"tag\" href=\"{}tags/{}.html\">{}</a> ",
+            root,
+            tag_slugs[tag.as_str()],
+            escape(tag)
+        ));
+    }
+    output.push_str("</p>\n");
+    output
+}
+
+fn search_index(entries: &[Entry]) -> SearchIndex<'_> {
+    let mut terms: BTreeMap<String, Vec<(usize, usize)>> = BTreeMap::new();
+    let mut docs = Vec::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        let mut add = |text: &str| {
+            for term in tokenize(text) {
+                *counts.entry(term).or_default() += 1;
+            }
+        };
+        add(&entry.title);
+        for turn in &entry.doc.turns {
+            add(&turn.user.content);
+            add(&turn.assistant);
+        }
+        for (term, count) in counts {
+            terms.entry(term).or_default().push((i, count));
+        }
+
+        docs.push(SearchDoc {
+            title: &entry.title,
+            url: format!("{}.html", entry.slug),
+            date: entry.date.as_deref(),
+        });
+    }
+
+    SearchIndex { docs, terms }
+}
+
+/// Lowercased alphanumeric words of two or more characters
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1)
+        .map(str::to_lowercase)
+}
+
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "conversation".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+fn unique_slug(base: &str, used: &mut HashSet<String>) -> String {
+    let mut slug = base.to_string();
+    let mut n = 2;
+    while !used.insert(slug.clone()) {
+        slug = format!("{}-{}", base, n);
+        n += 1;
+    }
+    slug
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(
+            slugify("team/Deploy Retro (2024)"),
+            "team-deploy-retro-2024"
+        );
+        assert_eq!(slugify("???"), "conversation");
+    }
+
+    #[test]
+    fn test_build_site() {
+        let root = std::env::temp_dir().join(format!("cmf-site-test-{}", std::process::id()));
+        let input = root.join("in");
+        let output = root.join("out");
+        fs::create_dir_all(input.join("team")).unwrap();
+        fs::write(
+            input.join("older.cmf"),
+            "---\ntitle: Older\ndate: 2024-01-01\ntags: [infra]\n---\n> Kubernetes?\nYes.",
+        )
+        .unwrap();
+        fs::write(
+            input.join("team/newer.cmf"),
+            "---\ntitle: Newer\ndate: 2024-06-01\ntags: [infra, rust]\n---\n> Cargo?\nYes.",
+        )
+        .unwrap();
+
+        let report = build(&input, &output).unwrap();
+        assert_eq!(
+            report,
+            SiteReport {
+                conversations: 2,
+                tags: 2
+            }
+        );
+
+        let index = fs::read_to_string(output.join("index.html")).unwrap();
+        assert!(index.find("Newer").unwrap() < index.find("Older").unwrap());
+        assert!(output.join("team-newer.html").exists());
+        let tag_page = fs::read_to_string(output.join("tags/infra.html")).unwrap();
+        assert!(tag_page.contains("../older.html"));
+
+        let search: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(output.join("search-index.json")).unwrap())
+                .unwrap();
+        assert_eq!(search["docs"][0]["title"], "Newer");
+        assert_eq!(search["terms"]["kubernetes"][0][0], 1);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_reserved_slugs() {
+        let root = std::env::temp_dir().join(format!("cmf-site-reserved-{}", std::process::id()));
+        let input = root.join("in");
+        let output = root.join("out");
+        fs::create_dir_all(&input).unwrap();
+        fs::write(
+            input.join("INDEX.cmf"),
+            "---\ntitle: Index talk\n---\n> Hi\nHello.",
+        )
+        .unwrap();
+
+        build(&input, &output).unwrap();
+        let index = fs::read_to_string(output.join("index.html")).unwrap();
+        assert!(index.contains("href=\"index-2.html\""));
+        assert!(fs::read_to_string(output.join("index-2.html"))
+            .unwrap()
+            .contains("Hello."));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+//! Splitting a conversation into parts and joining conversations together
+//!
+//! Every part of a split keeps the frontmatter of the whole. Joining keeps
+//! the first value of each frontmatter key, except `tags`, which are merged.
+//! Both can record where the turns came from: split parts get `source` and
+//! `source_turns` frontmatter, and joined conversations get a `source`
+//! annotation on the first user message of each part.
+
+use crate::{Document, EditError};
+
+/// Frontmatter keys describing where a single file came from, which no
+/// longer hold once other conversations are joined to it
+const PROVENANCE_KEYS: [&str; 4] = ["parent", "forked_at", "source", "source_turns"];
+
+impl Document {
+    /// Split into consecutive parts, a new part starting at each turn index
+    /// in `at`
+    ///
+    /// With a `source`, each part records it along with the range of turns
+    /// it holds (`source_turns: 6-12`, counting from 1).
+    pub fn split(&self, at: &[usize], source: Option<&str>) -> Result<Vec<Document>, EditError> {
+        let len = self.turns.len();
+        let mut bounds = at.to_vec();
+        bounds.sort_unstable();
+        bounds.dedup();
+        if let Some(&index) = bounds.iter().find(|&&index| index == 0 || index >= len) {
+            return Err(EditError::OutOfRange { index, len });
+        }
+        bounds.insert(0, 0);
+        bounds.push(len);
+
+        let parts = bounds
+            .windows(2)
+            .map(|range| {
+                let mut part = Document {
+                    meta: self.meta.clone(),
+                    turns: self.turns[range[0]..range[1]].to_vec(),
+                    preamble: self.preamble.clone(),
+                };
+                if let Some(source) = source {
+                    part.meta.insert("source".to_string(), source.to_string());
+                    part.meta.insert(
+                        "source_turns".to_string(),
+                        format!("{}-{}", range[0] + 1, range[1]),
+                    );
+                }
+                part
+            })
+            .collect();
+        Ok(parts)
+    }
+
+    /// Split into parts of `every` turns each (the last may be shorter)
+    pub fn split_every(
+        &self,
+        every: usize,
+        source: Option<&str>,
+    ) -> Result<Vec<Document>, EditError> {
+        let every = every.max(1);
+        let at: Vec<usize> = (every..self.turns.len()).step_by(every).collect();
+        self.split(&at, source)
+    }
+
+    /// Append the turns of `other`, merging its frontmatter into this one's
+    ///
+    /// Keys already set here win, `tags` become the union of both lists, and
+    /// fork and split links are dropped once a second part is joined. With a
+    /// `source`, the first user message of the appended part records it.
+    pub fn append_document(&mut self, other: &Document, source: Option<&str>) {
+        if self.turns.is_empty() && self.meta.is_empty() {
+            self.preamble = other.preamble.clone();
+        }
+        let joined = !self.turns.is_empty();
+        if joined {
+            for key in PROVENANCE_KEYS {
+                self.meta.remove(key);
+            }
+        }
+
+        let mut tags = self.tags();
+        for tag in other.tags() {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        for (key, value) in &other.meta {
+            if joined && PROVENANCE_KEYS.contains(&key.as_str()) {
+                continue;
+            }
+            self.meta
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        if !tags.is_empty() {
+            self.meta
+                .insert("tags".to_string(), format!("[{}]", tags.join(", ")));
+        }
+
+        let start = self.turns.len();
+        self.turns.extend(other.turns.iter().cloned());
+        if let (Some(source), Some(first)) = (source, self.turns.get_mut(start)) {
+            first
+                .user
+                .meta
+                .insert("source".to_string(), source.to_string());
+        }
+    }
+}
+
+/// Join conversations in order, as [`Document::append_document`] does
+pub fn concat<'a>(parts: impl IntoIterator<Item = (&'a Document, Option<&'a str>)>) -> Document {
+    let mut doc = Document::default();
+    for (part, source) in parts {
+        doc.append_document(part, source);
+    }
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAT: &str =
+        "---\ntitle: Planning\ntags: [work]\n---\n\n> a\nA\n\n> b\nB\n\n> c\nC\n\n> d\nD\n\n> e\nE";
+
+    fn users(doc: &Document) -> Vec<&str> {
+        doc.turns.iter().map(|t| t.user.content.as_str()).collect()
+    }
+
+    #[test]
+    fn test_split() {
+        let doc = Document::parse(CHAT);
+        let parts = doc.split(&[3, 1], None).unwrap();
+        let turns: Vec<Vec<&str>> = parts.iter().map(users).collect();
+        assert_eq!(turns, vec![vec!["a"], vec!["b", "c"], vec!["d", "e"]]);
+        assert_eq!(
+            parts[1].to_cmf(),
+            "---\ntitle: Planning\ntags: [work]\n---\n\n> b\nB\n\n> c\nC"
+        );
+
+        let parts = doc.split_every(2, Some("chat.cmf")).unwrap();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[1].meta["source"], "chat.cmf");
+        assert_eq!(parts[1].meta["source_turns"], "3-4");
+        assert_eq!(parts[2].meta["source_turns"], "5-5");
+
+        assert_eq!(
+            doc.split(&[5], None),
+            Err(EditError::OutOfRange { index: 5, len: 5 })
+        );
+    }
+
+    #[test]
+    fn test_concat() {
+        let first = Document::parse("---\ntitle: Monday\ntags: [work, rust]\n---\n\n> a\nA");
+        let second = Document::parse(
+            "---\ntitle: Tuesday\ntags: [rust, cli]\nsystem: Be brief.\nparent: x.cmf\n---\n\n> b\nB",
+        );
+        let doc = concat([(&first, None), (&second, Some("tuesday.cmf"))]);
+        assert_eq!(
+            doc.to_cmf(),
+            "---\nsystem: Be brief.\ntags: [work, rust, cli]\ntitle: Monday\n---\n\n\
+             > a\nA\n\n> b\n> <!-- cmf: source=tuesday.cmf -->\nB"
+        );
+
+        // Splitting with sources and joining again gives back the conversation
+        let parts = Document::parse(CHAT).split(&[2], Some("chat.cmf")).unwrap();
+        let joined = concat(parts.iter().map(|part| (part, None)));
+        assert_eq!(joined, Document::parse(CHAT));
+    }
+}
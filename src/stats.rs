@@ -0,0 +1,229 @@
+//! Counting what a conversation holds
+//!
+//! Stats from several conversations add up with [`Stats::add`], and
+//! [`Stats::averages`] spreads the totals over the conversations. Token
+//! counts here are estimates of about four characters per token; alternate
+//! replies are not counted.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::Document;
+
+/// Counts for one kind of message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Totals {
+    pub messages: usize,
+    pub words: usize,
+    pub chars: usize,
+    /// Estimated tokens
+    pub tokens: usize,
+}
+
+impl Totals {
+    fn count(&mut self, text: &str) {
+        self.messages += 1;
+        self.words += text.split_whitespace().count();
+        self.chars += text.chars().count();
+        self.tokens += estimate_tokens(text);
+    }
+
+    fn add(&mut self, other: &Totals) {
+        self.messages += other.messages;
+        self.words += other.words;
+        self.chars += other.chars;
+        self.tokens += other.tokens;
+    }
+}
+
+/// The turn with the most words
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Longest {
+    /// The file the turn is in, when stats span several
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Turn number, from 1
+    pub turn: usize,
+    pub words: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub conversations: usize,
+    pub turns: usize,
+    /// Totals by role: `system`, `user` and `assistant`
+    pub roles: BTreeMap<String, Totals>,
+    /// User messages by username
+    pub usernames: BTreeMap<String, usize>,
+    /// Fenced code blocks by language, `""` for none given
+    pub code_blocks: BTreeMap<String, usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longest_turn: Option<Longest>,
+}
+
+/// Totals spread over the conversations and turns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Averages {
+    pub turns_per_conversation: f64,
+    pub words_per_conversation: f64,
+    pub tokens_per_conversation: f64,
+    pub words_per_turn: f64,
+}
+
+impl Document {
+    /// Message, word and code block counts for the conversation
+    pub fn stats(&self) -> Stats {
+        self.stats_for(&(0..self.turns.len()).collect::<Vec<_>>())
+    }
+
+    /// Counts for only the turns at `turns`, such as those a
+    /// [`Selector`](crate::select::Selector) picks
+    ///
+    /// The longest turn keeps its position in the whole conversation.
+    pub fn stats_for(&self, turns: &[usize]) -> Stats {
+        let mut stats = Stats {
+            conversations: 1,
+            turns: turns.len(),
+            ..Default::default()
+        };
+        if let Some(system) = self.system() {
+            stats.role("system").count(system);
+        }
+        for &i in turns {
+            let turn = &self.turns[i];
+            stats.role("user").count(&turn.user.content);
+            if let Some(username) = &turn.user.username {
+                *stats.usernames.entry(username.clone()).or_default() += 1;
+            }
+            if !turn.assistant.is_empty() {
+                stats.role("assistant").count(&turn.assistant);
+            }
+            for language in turn.code_blocks() {
+                *stats.code_blocks.entry(language).or_default() += 1;
+            }
+
+            let words = turn.user.content.split_whitespace().count()
+                + turn.assistant.split_whitespace().count();
+            if stats.longest_turn.as_ref().is_none_or(|l| words > l.words) {
+                stats.longest_turn = Some(Longest {
+                    file: None,
+                    turn: i + 1,
+                    words,
+                });
+            }
+        }
+        stats
+    }
+}
+
+impl Stats {
+    fn role(&mut self, role: &str) -> &mut Totals {
+        self.roles.entry(role.to_string()).or_default()
+    }
+
+    /// Add the counts of other conversations
+    pub fn add(&mut self, other: &Stats) {
+        self.conversations += other.conversations;
+        self.turns += other.turns;
+        for (role, totals) in &other.roles {
+            self.role(role).add(totals);
+        }
+        for (username, count) in &other.usernames {
+            *self.usernames.entry(username.clone()).or_default() += count;
+        }
+        for (language, count) in &other.code_blocks {
+            *self.code_blocks.entry(language.clone()).or_default() += count;
+        }
+        if let Some(longest) = &other.longest_turn {
+            if self
+                .longest_turn
+                .as_ref()
+                .is_none_or(|l| longest.words > l.words)
+            {
+                self.longest_turn = Some(longest.clone());
+            }
+        }
+    }
+
+    pub fn averages(&self) -> Averages {
+        let total = |field: fn(&Totals) -> usize| -> usize { self.roles.values().map(field).sum() };
+        let per = |count: usize, over: usize| {
+            if over == 0 {
+                0.0
+            } else {
+                count as f64 / over as f64
+            }
+        };
+        let words = total(|t| t.words);
+        Averages {
+            turns_per_conversation: per(self.turns, self.conversations),
+            words_per_conversation: per(words, self.conversations),
+            tokens_per_conversation: per(total(|t| t.tokens), self.conversations),
+            words_per_turn: per(words, self.turns),
+        }
+    }
+}
+
+/// Roughly four characters per token, the usual rule of thumb for English
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let doc = Document::parse(
+            "---\nsystem: Be brief.\n---\n\n> @alice: Show me hello world\n```rust\nfn main() {}\n```\n\n> @bob: In Python?\n```python\nprint(1)\n```\n\n```\n$ python hello.py\n```\n\n> @alice: Thanks\n",
+        );
+        let stats = doc.stats();
+        assert_eq!(stats.turns, 3);
+        assert_eq!(stats.roles["system"].messages, 1);
+        assert_eq!(stats.roles["user"].messages, 3);
+        assert_eq!(stats.roles["user"].words, 7);
+        assert_eq!(stats.roles["assistant"].messages, 2);
+        assert_eq!(stats.usernames["alice"], 2);
+        assert_eq!(stats.code_blocks["rust"], 1);
+        assert_eq!(stats.code_blocks[""], 1);
+        assert_eq!(stats.longest_turn.as_ref().unwrap().turn, 2);
+        assert_eq!(stats.roles["user"].chars, 35);
+        assert_eq!(stats.roles["user"].tokens, 5 + 3 + 2);
+    }
+
+    #[test]
+    fn test_stats_for_selected_turns() {
+        let doc = Document::parse(
+            "> @alice: One\nA reply\n<!-- cmf: score=5 -->\n\n> @bob: Two three\nA much longer reply\n<!-- cmf: score=2 -->\n\n> @alice: Four\n```rust\nfn main() {}\n```\n<!-- cmf: score=4 -->",
+        );
+        let selector: crate::select::Selector = "[score>=4]".parse().unwrap();
+        let all = doc.stats();
+        let stats = doc.stats_for(&selector.select(&doc));
+        assert_eq!(all.turns, 3);
+        assert_eq!(stats.turns, 2);
+        assert_eq!(stats.roles["user"].words, 2);
+        assert_eq!(all.roles["user"].words, 4);
+        assert_eq!(stats.usernames.get("bob"), None);
+        assert_eq!(stats.code_blocks["rust"], 1);
+        // Positions are those in the whole conversation
+        assert_eq!(stats.longest_turn.as_ref().unwrap().turn, 3);
+        assert_eq!(all.longest_turn.as_ref().unwrap().turn, 2);
+    }
+
+    #[test]
+    fn test_totals_and_averages() {
+        let mut stats = Document::parse("> one two\nthree").stats();
+        let other = Document::parse("> a\nb c d e\n\n> f\ng").stats();
+        stats.add(&other);
+        assert_eq!(stats.conversations, 2);
+        assert_eq!(stats.roles["assistant"].words, 6);
+        assert_eq!(stats.longest_turn.as_ref().unwrap().words, 5);
+
+        let averages = stats.averages();
+        assert_eq!(averages.turns_per_conversation, 1.5);
+        assert_eq!(averages.words_per_conversation, 5.0);
+        assert_eq!(averages.words_per_turn, 10.0 / 3.0);
+    }
+}
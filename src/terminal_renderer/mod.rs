@@ -15,9 +15,11 @@ pub mod element_renderer;
 pub mod formatters;
 pub mod renderers;
 pub mod renderer;
+pub mod stream;
 
 // Re-export public API
 pub use context::{RenderContext, FormattingState};
 pub use element_renderer::ElementRenderer;
 pub use renderer::MarkdownRenderer;
+pub use stream::StreamRenderer;
 pub use renderers::{CodeBlockRenderer, TableRenderer, BlockquoteRenderer, ListRenderer};
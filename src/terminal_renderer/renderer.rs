@@ -12,6 +12,12 @@ pub struct MarkdownRenderer {
     use_colors: bool,
 }
 
+impl Default for MarkdownRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MarkdownRenderer {
     pub fn new() -> Self {
         Self {
@@ -206,8 +212,6 @@ impl MarkdownRenderer {
                         renderer.handle_soft_break(&mut context);
                     } else if let Some(ref mut renderer) = blockquote_renderer {
                         renderer.handle_soft_break(&mut context);
-                    } else if in_list_item {
-                        context.push_str(" ");
                     } else {
                         context.push_str(" ");
                     }
@@ -306,7 +310,7 @@ impl MarkdownRenderer {
                 output.push('┴');
             }
         }
-        output.push_str("┘");
+        output.push('┘');
 
         output
     }
@@ -9,6 +9,12 @@ pub struct BlockquoteRenderer {
     current_line: String,
 }
 
+impl Default for BlockquoteRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BlockquoteRenderer {
     pub fn new() -> Self {
         Self {
@@ -8,6 +8,12 @@ pub struct CodeBlockRenderer {
     buffer: String,
 }
 
+impl Default for CodeBlockRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CodeBlockRenderer {
     pub fn new() -> Self {
         Self {
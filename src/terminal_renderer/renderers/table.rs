@@ -10,6 +10,12 @@ pub struct TableRenderer {
     current_cell: String,
 }
 
+impl Default for TableRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TableRenderer {
     pub fn new() -> Self {
         Self {
@@ -88,7 +94,7 @@ impl TableRenderer {
                 output.push('┴');
             }
         }
-        output.push_str("┘");
+        output.push('┘');
 
         output
     }
@@ -0,0 +1,111 @@
+//! Incremental rendering of markdown that arrives in pieces
+//!
+//! Text is held back until a block is complete (a blank line outside a code
+//! fence), so each block is rendered once, with all of its context.
+
+use crate::terminal_renderer::renderer::MarkdownRenderer;
+
+pub struct StreamRenderer {
+    renderer: MarkdownRenderer,
+    pending: String,
+    started: bool,
+}
+
+impl Default for StreamRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamRenderer {
+    pub fn new() -> Self {
+        Self::with_renderer(MarkdownRenderer::new())
+    }
+
+    pub fn with_renderer(renderer: MarkdownRenderer) -> Self {
+        Self {
+            renderer,
+            pending: String::new(),
+            started: false,
+        }
+    }
+
+    /// Add streamed text, returning the rendered output of any blocks it completes
+    pub fn push(&mut self, text: &str) -> String {
+        self.pending.push_str(text);
+        match self.complete_blocks_end() {
+            Some(end) => {
+                let blocks: String = self.pending.drain(..end).collect();
+                self.render_blocks(&blocks)
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Render whatever is left once the stream ends
+    pub fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.pending);
+        let mut output = self.render_blocks(&rest);
+        if self.started {
+            output.push('\n');
+        }
+        self.started = false;
+        output
+    }
+
+    /// Byte offset just past the last blank line outside a code fence
+    fn complete_blocks_end(&self) -> Option<usize> {
+        let mut in_fence = false;
+        let mut end = None;
+        let mut offset = 0;
+        for line in self.pending.split_inclusive('\n') {
+            offset += line.len();
+            if !line.ends_with('\n') {
+                break;
+            }
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_fence = !in_fence;
+            } else if !in_fence && trimmed.trim().is_empty() {
+                end = Some(offset);
+            }
+        }
+        end
+    }
+
+    fn render_blocks(&mut self, blocks: &str) -> String {
+        if blocks.trim().is_empty() {
+            return String::new();
+        }
+        let rendered = self.renderer.render(blocks.trim_matches('\n'));
+        let mut output = String::new();
+        if self.started {
+            output.push_str("\n\n");
+        }
+        output.push_str(rendered.trim_end());
+        self.started = true;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_renders_complete_blocks() {
+        let mut stream = StreamRenderer::new();
+        assert_eq!(stream.push("First para"), "");
+        assert_eq!(stream.push("graph\n\nSec"), "First paragraph");
+        assert_eq!(stream.push("ond\n"), "");
+        assert_eq!(stream.finish(), "\n\nSecond\n");
+    }
+
+    #[test]
+    fn test_holds_back_open_code_fence() {
+        let mut stream = StreamRenderer::new();
+        assert_eq!(stream.push("```\nfn a() {}\n\nfn b() {}\n"), "");
+        let output = stream.push("```\n\n");
+        assert!(output.contains("fn a() {}") && output.contains("fn b() {}"));
+    }
+}
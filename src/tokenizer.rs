@@ -0,0 +1,428 @@
+//! Exact token counts with OpenAI's BPE encodings
+//!
+//! The vocabularies are the `.tiktoken` files OpenAI publishes (one base64
+//! token and its rank per line). They are too large to bundle, so they are
+//! loaded from disk: from a path given explicitly, or from
+//! `$CMF_TIKTOKEN_DIR` (default `~/.cache/cmf/tiktoken`) under their
+//! encoding name, e.g. `o200k_base.tiktoken`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use regex::Regex;
+
+use crate::{stats, Document, Turn};
+
+/// Tokens the Chat Completions format adds around every message
+const TOKENS_PER_MESSAGE: usize = 3;
+/// Tokens that prime the reply after the last message
+const TOKENS_PER_REPLY: usize = 3;
+
+const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+";
+
+const O200K_PATTERN: &str = r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+";
+
+/// A BPE encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// GPT-3.5 and GPT-4
+    Cl100kBase,
+    /// GPT-4o and later
+    O200kBase,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Cl100kBase => "cl100k_base",
+            Encoding::O200kBase => "o200k_base",
+        }
+    }
+
+    /// Where OpenAI publishes the vocabulary
+    pub fn url(self) -> String {
+        format!(
+            "https://openaipublic.blob.core.windows.net/encodings/{}.tiktoken",
+            self.name()
+        )
+    }
+
+    /// Where [`Tokenizer::open`] looks for the vocabulary
+    pub fn default_path(self) -> Option<PathBuf> {
+        let dir = match std::env::var_os("CMF_TIKTOKEN_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => Path::new(&std::env::var_os("HOME")?).join(".cache/cmf/tiktoken"),
+        };
+        Some(dir.join(format!("{}.tiktoken", self.name())))
+    }
+
+    /// The pre-tokenizer pattern, minus the `\s+(?!\S)` lookahead the
+    /// `regex` crate lacks; [`Tokenizer::pieces`] makes up for it
+    fn pattern(self) -> &'static str {
+        match self {
+            Encoding::Cl100kBase => CL100K_PATTERN,
+            Encoding::O200kBase => O200K_PATTERN,
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = TokenizerError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "cl100k_base" => Ok(Encoding::Cl100kBase),
+            "o200k_base" => Ok(Encoding::O200kBase),
+            _ => Err(TokenizerError::UnknownEncoding(name.to_string())),
+        }
+    }
+}
+
+/// Why a tokenizer could not be loaded
+#[derive(Debug)]
+pub enum TokenizerError {
+    UnknownEncoding(String),
+    /// The vocabulary file is missing or unreadable
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// A vocabulary line is not `<base64> <rank>`
+    Invalid {
+        path: PathBuf,
+        line: usize,
+    },
+    /// The vocabulary is not at its default path, or there is none (no
+    /// `$HOME`)
+    Missing {
+        encoding: Encoding,
+        path: Option<PathBuf>,
+    },
+}
+
+impl std::fmt::Display for TokenizerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenizerError::UnknownEncoding(name) => write!(
+                f,
+                "unknown encoding {:?} (expected cl100k_base or o200k_base)",
+                name
+            ),
+            TokenizerError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            TokenizerError::Invalid { path, line } => {
+                write!(
+                    f,
+                    "{}:{}: not a tiktoken vocabulary line",
+                    path.display(),
+                    line
+                )
+            }
+            TokenizerError::Missing { encoding, path } => match path {
+                Some(path) => write!(
+                    f,
+                    "no vocabulary for {} at {}; download it from {}",
+                    encoding.name(),
+                    path.display(),
+                    encoding.url()
+                ),
+                None => write!(
+                    f,
+                    "no vocabulary for {}; download it from {}",
+                    encoding.name(),
+                    encoding.url()
+                ),
+            },
+        }
+    }
+}
+
+impl std::error::Error for TokenizerError {}
+
+/// A byte-level BPE tokenizer
+#[derive(Debug, Clone)]
+pub struct Tokenizer {
+    encoding: Encoding,
+    ranks: HashMap<Vec<u8>, u32>,
+    regex: Regex,
+}
+
+impl Tokenizer {
+    /// A tokenizer from a vocabulary of token bytes and their ranks, which
+    /// must hold every single byte
+    pub fn from_ranks(encoding: Encoding, ranks: HashMap<Vec<u8>, u32>) -> Self {
+        Tokenizer {
+            encoding,
+            ranks,
+            regex: Regex::new(encoding.pattern()).unwrap(),
+        }
+    }
+
+    /// Load the vocabulary from a `.tiktoken` file
+    pub fn load(encoding: Encoding, path: &Path) -> Result<Self, TokenizerError> {
+        let content = fs::read_to_string(path).map_err(|error| TokenizerError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        let mut ranks = HashMap::new();
+        for (i, line) in content.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || TokenizerError::Invalid {
+                path: path.to_path_buf(),
+                line: i + 1,
+            };
+            let (token, rank) = line.split_once(' ').ok_or_else(invalid)?;
+            let token = decode_base64(token).ok_or_else(invalid)?;
+            let rank = rank.parse().map_err(|_| invalid())?;
+            ranks.insert(token, rank);
+        }
+        Ok(Self::from_ranks(encoding, ranks))
+    }
+
+    /// Load the vocabulary from its default path
+    pub fn open(encoding: Encoding) -> Result<Self, TokenizerError> {
+        match encoding.default_path() {
+            Some(path) if path.exists() => Self::load(encoding, &path),
+            path => Err(TokenizerError::Missing { encoding, path }),
+        }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// The token ranks of `text`
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        let mut tokens = Vec::new();
+        for piece in self.pieces(text) {
+            let piece = piece.as_bytes();
+            if let Some(&rank) = self.ranks.get(piece) {
+                tokens.push(rank);
+                continue;
+            }
+            for part in self.merge(piece) {
+                tokens.push(self.ranks.get(part).copied().unwrap_or(u32::MAX));
+            }
+        }
+        tokens
+    }
+
+    /// Number of tokens in `text`
+    pub fn count(&self, text: &str) -> usize {
+        self.pieces(text)
+            .map(|piece| {
+                if self.ranks.contains_key(piece.as_bytes()) {
+                    1
+                } else {
+                    self.merge(piece.as_bytes()).len()
+                }
+            })
+            .sum()
+    }
+
+    /// Split text the way the encoding's pre-tokenizer does
+    ///
+    /// A run of whitespace followed by more text leaves its last character
+    /// to start the next piece, as the original `\s+(?!\S)` does.
+    fn pieces<'a>(&'a self, text: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            let found = self.regex.find_at(text, pos)?;
+            let mut end = found.end();
+            let piece = found.as_str();
+            let is_space_run = piece.chars().all(char::is_whitespace)
+                && !piece.ends_with(['\r', '\n'])
+                && end < text.len();
+            if is_space_run && piece.chars().count() > 1 {
+                end -= piece.chars().next_back().unwrap().len_utf8();
+            }
+            pos = end;
+            Some(&text[found.start()..end])
+        })
+    }
+
+    /// Merge the bytes of a piece pair by pair, lowest rank first
+    fn merge<'a>(&self, piece: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut bounds: Vec<usize> = (0..=piece.len()).collect();
+        while bounds.len() > 2 {
+            let best = (0..bounds.len() - 2)
+                .filter_map(|i| {
+                    let rank = self.ranks.get(&piece[bounds[i]..bounds[i + 2]])?;
+                    Some((*rank, i))
+                })
+                .min();
+            match best {
+                Some((_, i)) => {
+                    bounds.remove(i + 1);
+                }
+                None => break,
+            }
+        }
+        bounds.windows(2).map(|w| &piece[w[0]..w[1]]).collect()
+    }
+}
+
+/// Counts the tokens in text: a [`Tokenizer`], or the rough [`Estimate`]
+pub trait Count {
+    fn count(&self, text: &str) -> usize;
+}
+
+impl Count for Tokenizer {
+    fn count(&self, text: &str) -> usize {
+        Tokenizer::count(self, text)
+    }
+}
+
+/// About four characters per token, for when no vocabulary is at hand
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Estimate;
+
+impl Count for Estimate {
+    fn count(&self, text: &str) -> usize {
+        stats::estimate_tokens(text)
+    }
+}
+
+fn message_tokens(counter: &impl Count, role: &str, content: &str) -> usize {
+    TOKENS_PER_MESSAGE + counter.count(role) + counter.count(content)
+}
+
+impl Turn {
+    /// Tokens the turn's user message and reply take in a Chat Completions
+    /// request
+    pub fn token_count(&self, counter: &impl Count) -> usize {
+        let mut tokens = message_tokens(counter, "user", &self.user.content);
+        if !self.assistant.is_empty() {
+            tokens += message_tokens(counter, "assistant", &self.assistant);
+        }
+        tokens
+    }
+}
+
+impl Document {
+    /// Tokens the conversation takes as a Chat Completions request: the
+    /// system prompt and every message, each with its per-message overhead,
+    /// plus the tokens that prime the reply
+    pub fn token_count(&self, counter: &impl Count) -> usize {
+        self.base_token_count(counter)
+            + self
+                .turns
+                .iter()
+                .map(|turn| turn.token_count(counter))
+                .sum::<usize>()
+    }
+
+    /// Tokens the request takes besides the turns
+    pub(crate) fn base_token_count(&self, counter: &impl Count) -> usize {
+        let system = self
+            .system()
+            .map_or(0, |system| message_tokens(counter, "system", system));
+        system + TOKENS_PER_REPLY
+    }
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    let text = text.trim_end_matches('=').as_bytes();
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &c in text {
+        buffer = (buffer << 6) | value(c)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every byte, plus a few merges
+    fn tokenizer(encoding: Encoding) -> Tokenizer {
+        let mut ranks: HashMap<Vec<u8>, u32> = (0..=255u8).map(|b| (vec![b], b as u32)).collect();
+        for (i, token) in ["he", "ll", "llo", "hello", " w", "or", " wor"]
+            .iter()
+            .enumerate()
+        {
+            ranks.insert(token.as_bytes().to_vec(), 256 + i as u32);
+        }
+        Tokenizer::from_ranks(encoding, ranks)
+    }
+
+    #[test]
+    fn test_pieces() {
+        let tokenizer = tokenizer(Encoding::Cl100kBase);
+        let pieces: Vec<&str> = tokenizer.pieces("Hello world  !\n\n x 1234").collect();
+        assert_eq!(
+            pieces,
+            vec!["Hello", " world", " ", " !\n\n", " x", " ", "123", "4"]
+        );
+        let pieces: Vec<&str> = tokenizer.pieces("don't   \n").collect();
+        assert_eq!(pieces, vec!["don", "'t", "   \n"]);
+
+        let tokenizer = self::tokenizer(Encoding::O200kBase);
+        let pieces: Vec<&str> = tokenizer.pieces("HelloWorld don't").collect();
+        assert_eq!(pieces, vec!["Hello", "World", " don't"]);
+    }
+
+    #[test]
+    fn test_merges() {
+        let tokenizer = tokenizer(Encoding::Cl100kBase);
+        assert_eq!(tokenizer.encode("hello"), vec![259]);
+        // " world" merges " w" + "or" into " wor", then "l" and "d" stay
+        assert_eq!(
+            tokenizer.encode(" world"),
+            vec![262, b'l' as u32, b'd' as u32]
+        );
+        assert_eq!(tokenizer.count("hello world"), 4);
+    }
+
+    #[test]
+    fn test_token_count() {
+        let tokenizer = tokenizer(Encoding::Cl100kBase);
+        let doc = Document::parse("---\nsystem: hi\n---\n\n> hello\nhello");
+        // Each message: 3 + role + content; then 3 for the reply
+        let role = |role: &str| tokenizer.count(role);
+        assert_eq!(
+            doc.token_count(&Estimate),
+            (3 + 2 + 1) + (3 + 1 + 2) + (3 + 3 + 2) + 3
+        );
+        assert_eq!(
+            doc.token_count(&tokenizer),
+            (3 + role("system") + 2) + (3 + role("user") + 1) + (3 + role("assistant") + 1) + 3
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join(format!("cmf-vocab-{}.tiktoken", std::process::id()));
+        fs::write(&path, "aGVsbG8= 0\nIQ== 1\n").unwrap();
+        let tokenizer = Tokenizer::load(Encoding::O200kBase, &path).unwrap();
+        assert_eq!(tokenizer.encode("hello!"), vec![0, 1]);
+
+        fs::write(&path, "aGVsbG8=\n").unwrap();
+        assert!(matches!(
+            Tokenizer::load(Encoding::O200kBase, &path),
+            Err(TokenizerError::Invalid { line: 1, .. })
+        ));
+        fs::remove_file(&path).unwrap();
+
+        assert!("p50k_base".parse::<Encoding>().is_err());
+    }
+}
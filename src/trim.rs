@@ -0,0 +1,262 @@
+//! Fitting a conversation into a token budget
+//!
+//! [`Document::fit_to_budget`] applies strategies in order, each shrinking
+//! the conversation only as far as needed, until it fits. The system prompt
+//! and the final turn are never touched, so the request still ends with the
+//! message the model should answer.
+
+use crate::tokenizer::Count;
+use crate::Document;
+
+/// Replies are never shortened below this many tokens
+const MIN_REPLY_TOKENS: usize = 32;
+
+/// Put in place of the middle of a shortened reply
+const OMITTED: &str = "\n\n[…]\n\n";
+
+/// A way to shrink a conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Drop turns from the start
+    DropOldest,
+    /// Drop turns from the middle, oldest first, keeping the first `first`
+    /// and the last `last`
+    KeepEnds { first: usize, last: usize },
+    /// Cut the middle out of the longest assistant replies
+    TruncateReplies,
+}
+
+/// The result of fitting a conversation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fit {
+    pub doc: Document,
+    /// Indices of the dropped turns in the original conversation
+    pub dropped: Vec<usize>,
+    /// Indices of the turns whose reply was shortened, in the original
+    /// conversation
+    pub truncated: Vec<usize>,
+    pub tokens_before: usize,
+    pub tokens: usize,
+    /// Whether `tokens` is within the budget
+    pub fits: bool,
+}
+
+impl Document {
+    /// Shrink the conversation to at most `budget` tokens
+    ///
+    /// Strategies are tried in order until the conversation fits; if none
+    /// get there, [`Fit::fits`] is false and the result is as small as they
+    /// could make it.
+    pub fn fit_to_budget(
+        &self,
+        budget: usize,
+        strategies: &[Strategy],
+        counter: &impl Count,
+    ) -> Fit {
+        let base = self.base_token_count(counter);
+        // Each kept turn with its index in `self` and its token count
+        let mut turns: Vec<(usize, usize)> = self
+            .turns
+            .iter()
+            .enumerate()
+            .map(|(i, turn)| (i, turn.token_count(counter)))
+            .collect();
+        let mut doc = self.clone();
+        let total = |turns: &[(usize, usize)]| base + turns.iter().map(|(_, t)| t).sum::<usize>();
+        let tokens_before = total(&turns);
+        let mut truncated = Vec::new();
+
+        for strategy in strategies {
+            if total(&turns) <= budget {
+                break;
+            }
+            match *strategy {
+                Strategy::DropOldest => drop_turns(&mut doc, &mut turns, budget, base, 0, 1),
+                Strategy::KeepEnds { first, last } => {
+                    drop_turns(&mut doc, &mut turns, budget, base, first, last.max(1))
+                }
+                Strategy::TruncateReplies => {
+                    truncate_replies(&mut doc, &mut turns, budget, base, counter, &mut truncated)
+                }
+            }
+        }
+
+        let kept: Vec<usize> = turns.iter().map(|(i, _)| *i).collect();
+        let dropped = (0..self.turns.len())
+            .filter(|i| !kept.contains(i))
+            .collect();
+        truncated.retain(|i| kept.contains(i));
+        truncated.sort_unstable();
+        let tokens = total(&turns);
+        Fit {
+            doc,
+            dropped,
+            truncated,
+            tokens_before,
+            tokens,
+            fits: tokens <= budget,
+        }
+    }
+}
+
+/// Drop turns after the first `first`, oldest first, until the
+/// conversation fits or only `first` and `last` turns are left
+fn drop_turns(
+    doc: &mut Document,
+    turns: &mut Vec<(usize, usize)>,
+    budget: usize,
+    base: usize,
+    first: usize,
+    last: usize,
+) {
+    let mut total = base + turns.iter().map(|(_, t)| t).sum::<usize>();
+    while total > budget && turns.len() > first + last {
+        let (_, tokens) = turns.remove(first);
+        doc.turns.remove(first);
+        total -= tokens;
+    }
+}
+
+/// Shorten the longest replies, except the final turn's, until the
+/// conversation fits or no reply can get shorter
+fn truncate_replies(
+    doc: &mut Document,
+    turns: &mut [(usize, usize)],
+    budget: usize,
+    base: usize,
+    counter: &impl Count,
+    truncated: &mut Vec<usize>,
+) {
+    let mut done = vec![false; turns.len()];
+    loop {
+        let total = base + turns.iter().map(|(_, t)| t).sum::<usize>();
+        if total <= budget {
+            return;
+        }
+        let candidates = turns.len().saturating_sub(1);
+        let Some(k) = (0..candidates)
+            .filter(|&k| !done[k])
+            .max_by_key(|&k| (counter.count(&doc.turns[k].assistant), std::cmp::Reverse(k)))
+        else {
+            return;
+        };
+
+        let reply = &doc.turns[k].assistant;
+        let reply_tokens = counter.count(reply);
+        let target = reply_tokens
+            .saturating_sub(total - budget)
+            .max(MIN_REPLY_TOKENS);
+        done[k] = true;
+        if target >= reply_tokens {
+            continue;
+        }
+
+        // Token counts are not proportional to characters, so cut a bit
+        // more until the reply is down to its target
+        let chars = reply.chars().count();
+        let mut keep = chars * target / reply_tokens;
+        let shortened = loop {
+            let shortened = cut_middle(reply, keep);
+            let over = counter.count(&shortened).saturating_sub(target);
+            if over == 0 || keep == 0 {
+                break shortened;
+            }
+            keep = keep.saturating_sub((over * chars / reply_tokens).max(1));
+        };
+        let turn = &mut doc.turns[k];
+        turn.assistant = shortened;
+        turn.span.clear();
+        turns[k].1 = turn.token_count(counter);
+        truncated.push(turns[k].0);
+    }
+}
+
+/// Keep about `keep` characters of `text`, half from each end
+fn cut_middle(text: &str, keep: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    if keep >= chars.len() {
+        return text.to_string();
+    }
+    let head: String = chars[..keep / 2].iter().collect();
+    let tail: String = chars[chars.len() - (keep - keep / 2)..].iter().collect();
+    format!("{}{}{}", head.trim_end(), OMITTED, tail.trim_start())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::Estimate;
+
+    fn chat(replies: &[&str]) -> Document {
+        let mut text = String::from("---\nsystem: Be brief.\n---");
+        for (i, reply) in replies.iter().enumerate() {
+            text.push_str(&format!("\n\n> question {}\n{}", i + 1, reply));
+        }
+        Document::parse(&text)
+    }
+
+    fn users(doc: &Document) -> Vec<&str> {
+        doc.turns.iter().map(|t| t.user.content.as_str()).collect()
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let doc = chat(&["one", "two", "three", "four", ""]);
+        let full = doc.token_count(&Estimate);
+        let turn = doc.turns[0].token_count(&Estimate);
+
+        let fit = doc.fit_to_budget(full - turn, &[Strategy::DropOldest], &Estimate);
+        assert!(fit.fits);
+        assert_eq!(fit.dropped, vec![0]);
+        assert_eq!(fit.tokens, fit.doc.token_count(&Estimate));
+        assert_eq!(fit.doc.system(), Some("Be brief."));
+
+        // Never the final turn, even if that does not fit
+        let fit = doc.fit_to_budget(1, &[Strategy::DropOldest], &Estimate);
+        assert!(!fit.fits);
+        assert_eq!(users(&fit.doc), vec!["question 5"]);
+        assert_eq!(fit.tokens_before, full);
+    }
+
+    #[test]
+    fn test_keep_ends() {
+        let doc = chat(&["one", "two", "three", "four", "five", ""]);
+        let strategy = Strategy::KeepEnds { first: 1, last: 2 };
+        let fit = doc.fit_to_budget(0, &[strategy], &Estimate);
+        assert_eq!(
+            users(&fit.doc),
+            vec!["question 1", "question 5", "question 6"]
+        );
+        assert_eq!(fit.dropped, vec![1, 2, 3]);
+
+        // Only as much as needed
+        let budget = doc.token_count(&Estimate) - 1;
+        let fit = doc.fit_to_budget(budget, &[strategy], &Estimate);
+        assert_eq!(fit.dropped, vec![1]);
+    }
+
+    #[test]
+    fn test_truncate_replies() {
+        let long = "word ".repeat(200);
+        let doc = chat(&["short", long.trim_end(), "short", ""]);
+        let budget = doc.token_count(&Estimate) - 100;
+        let fit = doc.fit_to_budget(budget, &[Strategy::TruncateReplies], &Estimate);
+        assert!(fit.fits);
+        assert_eq!(fit.truncated, vec![1]);
+        assert!(fit.dropped.is_empty());
+        let reply = &fit.doc.turns[1].assistant;
+        assert!(reply.starts_with("word word"));
+        assert!(reply.contains("[…]"));
+        assert!(reply.ends_with("word word"));
+        assert_eq!(Document::parse(&fit.doc.to_cmf()), fit.doc);
+
+        // Then drop turns if shortening replies is not enough
+        let fit = doc.fit_to_budget(
+            40,
+            &[Strategy::TruncateReplies, Strategy::DropOldest],
+            &Estimate,
+        );
+        assert_eq!(fit.truncated, Vec::<usize>::new());
+        assert_eq!(fit.dropped, vec![0, 1]);
+    }
+}